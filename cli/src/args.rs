@@ -1,7 +1,7 @@
 use std::{path::PathBuf, str::FromStr};
 
 use log::LogLevelFilter as LevelFilter;
-use monument::DebugOutput;
+use monument::{DebugOutput, Priority, Strategy, Transposition};
 use structopt::StructOpt;
 
 /// A struct storing the CLI args taken by Monument.  `StructOpt` will generate the argument
@@ -19,6 +19,36 @@ pub struct CliArgs {
     /// The maximum number of threads that Monument will use
     #[structopt(short = "Q", long)]
     pub queue_limit: Option<usize>,
+    /// The approximate maximum amount of memory used by the search, in bytes.  Can be suffixed with
+    /// `K`, `M` or `G` (e.g. `4G`).
+    #[structopt(short = "M", long, parse(try_from_str = parse_memory_size))]
+    pub memory_limit: Option<usize>,
+    /// Instead of discarding prefixes when the queue gets too long, write them to temporary files
+    /// in this directory and reload them later.  The directory is created if it doesn't exist.
+    #[structopt(long, parse(from_os_str))]
+    pub spill_dir: Option<PathBuf>,
+
+    /// Keep searching until the best compositions are guaranteed to have been found.  This is
+    /// only feasible for small searches.
+    #[structopt(long)]
+    pub exact: bool,
+    /// How the search chooses which prefix to expand next.  Options are `avg-score` (the default),
+    /// `estimated` and `upper-bound`.
+    #[structopt(long, parse(try_from_str = parse_priority))]
+    pub priority: Option<Priority>,
+    /// The algorithm used to search for compositions.  Options are `best-first` (the default),
+    /// `depth-first`, `enumerate`, `beam[=<width>]` and `random[=<num_restarts>]`.  `enumerate`
+    /// generates every valid composition, ignoring the queue limit and number of comps.
+    #[structopt(long, parse(try_from_str = parse_strategy))]
+    pub strategy: Option<Strategy>,
+    /// How prefixes which reach the same search state are merged.  Options are `off` (the
+    /// default), `exact` and `dominance`.
+    #[structopt(long, parse(try_from_str = parse_transposition))]
+    pub transposition: Option<Transposition>,
+    /// Instead of searching for new compositions, try to improve an existing composition (given
+    /// as a call string) by making small changes to it
+    #[structopt(short = "I", long)]
+    pub improve: Option<String>,
 
     /// Makes Monument print more output (`-vv` will produce all output).
     #[structopt(short, long = "verbose", parse(from_occurrences))]
@@ -48,6 +78,93 @@ impl CliArgs {
     }
 }
 
+/// Parse a number of bytes, optionally suffixed with `K`, `M` or `G` (e.g. `4G` or `500m`)
+fn parse_memory_size(v: &str) -> Result<usize, String> {
+    let v = v.trim();
+    let (num_str, multiplier) = match v.chars().last().map(|c| c.to_ascii_uppercase()) {
+        Some('K') => (&v[..v.len() - 1], 1 << 10),
+        Some('M') => (&v[..v.len() - 1], 1 << 20),
+        Some('G') => (&v[..v.len() - 1], 1 << 30),
+        _ => (v, 1),
+    };
+    let num = num_str
+        .trim()
+        .parse::<usize>()
+        .map_err(|e| format!("Invalid memory size {:?}: {}", v, e))?;
+    num.checked_mul(multiplier)
+        .ok_or_else(|| format!("Memory size {:?} is too large", v))
+}
+
+/// Parse the value of the `--priority` arg
+fn parse_priority(v: &str) -> Result<Priority, String> {
+    Ok(match v.to_lowercase().as_str() {
+        "avg-score" => Priority::AvgScore,
+        "estimated" => Priority::Estimated,
+        "upper-bound" => Priority::UpperBound,
+        _ => {
+            return Err(format!(
+                "Unknown value {:?}. Expected `avg-score`, `estimated` or `upper-bound`.",
+                v
+            ))
+        }
+    })
+}
+
+/// Parse the value of the `--strategy` arg
+fn parse_strategy(v: &str) -> Result<Strategy, String> {
+    const DEFAULT_BEAM_WIDTH: usize = 10_000;
+    const DEFAULT_NUM_RESTARTS: usize = 100_000;
+    const RANDOM_SEED: u64 = 0x5eed_1234_abcd_9876;
+
+    // Split `v` into a name and an optional numeric parameter (e.g. `beam=500`)
+    let lower_v = v.to_lowercase();
+    let (name, param) = match lower_v.split_once('=') {
+        Some((name, param_str)) => {
+            let param = param_str
+                .parse::<usize>()
+                .map_err(|e| format!("Invalid number {:?}: {}", param_str, e))?;
+            (name, Some(param))
+        }
+        None => (lower_v.as_str(), None),
+    };
+
+    Ok(match (name, param) {
+        ("best-first", None) => Strategy::BestFirst,
+        ("depth-first", None) => Strategy::DepthFirst,
+        ("enumerate", None) => Strategy::Enumerate,
+        ("beam", _) => match param.unwrap_or(DEFAULT_BEAM_WIDTH) {
+            0 => return Err("Beam width must be positive".to_owned()),
+            width => Strategy::Beam { width },
+        },
+        ("random", _) => Strategy::RandomRestarts {
+            num_restarts: param.unwrap_or(DEFAULT_NUM_RESTARTS),
+            seed: RANDOM_SEED,
+        },
+        _ => {
+            return Err(format!(
+                "Unknown value {:?}. Expected `best-first`, `depth-first`, `enumerate`, \
+                 `beam[=<width>]` or `random[=<num_restarts>]`.",
+                v
+            ))
+        }
+    })
+}
+
+/// Parse the value of the `--transposition` arg
+fn parse_transposition(v: &str) -> Result<Transposition, String> {
+    Ok(match v.to_lowercase().as_str() {
+        "off" => Transposition::Off,
+        "exact" => Transposition::Exact,
+        "dominance" => Transposition::Dominance,
+        _ => {
+            return Err(format!(
+                "Unknown value {:?}. Expected `off`, `exact` or `dominance`.",
+                v
+            ))
+        }
+    })
+}
+
 /// What item should be debug printed
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum DebugPrint {
@@ -11,7 +11,7 @@ use bellframe::{
     InvalidRowError,
 };
 use log::log;
-use monument::Config;
+use monument::{Config, Priority, QueryError, Strategy, Transposition};
 use spec::Spec;
 use structopt::StructOpt;
 
@@ -35,11 +35,25 @@ fn main() {
     )
     .unwrap();
 
+    // Generate config
+    let config = Config {
+        queue_limit: args.queue_limit.unwrap_or(DEFAULT_QUEUE_LIMIT),
+        memory_limit: args.memory_limit,
+        spill_dir: args.spill_dir.clone(),
+        num_threads: Some(1),
+        exact: args.exact,
+        priority: args.priority.unwrap_or(Priority::AvgScore),
+        strategy: args.strategy.unwrap_or(Strategy::BestFirst),
+        transposition: args.transposition.unwrap_or(Transposition::Off),
+        ..Config::default()
+    };
+
     // Run Monument
     run(
         &args.input_file,
         args.debug_print,
-        args.queue_limit.unwrap_or(DEFAULT_QUEUE_LIMIT),
+        args.improve.as_deref(),
+        config,
     )
     .unwrap();
 }
@@ -50,19 +64,55 @@ pub enum Error {
     NoMethods,
     CcLibNotFound,
     PartHeadParse(InvalidRowError),
+    StartRowParse(InvalidRowError),
+    EndRowParse(InvalidRowError),
+    /// A multi-part `part_head` was given with different `start_row` and `end_row`
+    MultiPartStartEndRows,
     SpecFile(PathBuf, spec::TomlReadError),
     MusicFile(PathBuf, spec::TomlReadError),
-    MethodNotFound { suggestions: Vec<String> },
+    MethodNotFound {
+        suggestions: Vec<String>,
+    },
     CallPnParse(String, place_not::ParseError),
     MethodPnParse(PnBlockParseError),
     LeadLocationIndex(String, ParseIntError),
     LayoutGen(monument::layout::new::Error),
+    /// `run_weights` was given a different number of weights to the number of `run_lengths`
+    RunWeightCount {
+        num_lengths: usize,
+        num_weights: usize,
+    },
+    /// `count` was given for runs which are split into several music types (by `run_weights` or
+    /// `separate_locations`), so it's ambiguous which music type the count applies to
+    RunCountSplit,
+    /// `count` was given for a music type which matches pairs of rows (i.e. `wrap_lengths` or
+    /// `row_pairs`).  Such music is also generated between nodes, so its counts can't be bounded
+    MultiRowCount,
+    /// `course_head_counts` was given for a `leadwise` composition, which has no courses (only
+    /// leads) so can't count them
+    LeadwiseCourseHeadCounts,
+    /// `max_overlap` wasn't between `0.0` and `1.0`
+    InvalidMaxOverlap(f32),
+    /// A coursing order mask either contained invalid bells or had the wrong length
+    InvalidCoursingOrder(String),
+    /// More than [`MAX_REQUIRED_ROWS`](monument::graph::MAX_REQUIRED_ROWS) required rows were
+    /// given
+    TooManyRequiredRows(usize),
+    /// The call string given to `--improve` isn't a composition which satisfies the spec
+    CompNotFound(String),
+    /// The `prefix` given in the spec can't be rung from any of the starts
+    PrefixNotFound(String),
+    /// `atw = true` was given, but some place bell can't be rung in full by any lead
+    UnrungPlaceBell(monument::graph::PlaceBell),
+    /// The `--spill-dir` couldn't be created or isn't writable
+    SpillDir(PathBuf, std::io::Error),
 }
 
 fn run(
     input_file: &Path,
     debug_print: Option<DebugPrint>,
-    queue_limit: usize,
+    improve: Option<&str>,
+    mut config: Config,
 ) -> Result<(), Error> {
     let start_time = Instant::now();
 
@@ -88,12 +138,17 @@ fn run(
     debug_print!(Query, query);
     debug_print!(Layout, &query.layout);
 
-    // Generate config
-    let mut config = Config {
-        queue_limit,
-        num_threads: Some(1),
-        ..Config::default()
-    };
+    // Improve an existing composition, if one is given
+    if let Some(call_string) = improve {
+        let comps = monument::improve_comp(&query, &mut config, call_string)
+            .ok_or_else(|| Error::CompNotFound(call_string.to_owned()))?;
+        println!("\n\n\n\nIMPROVEMENT COMPLETE!\n\n\n");
+        for c in comps {
+            c.long_string(&query.layout);
+        }
+        println!("Improvement completed in {:?}", Instant::now() - start_time);
+        return Ok(());
+    }
 
     // Run query and handle its debug output
     let query_result =
@@ -107,10 +162,13 @@ fn run(
 
             println!("Search completed in {:?}", Instant::now() - start_time);
         }
-        Err(Some(graph)) => {
+        Err(QueryError::Graph(graph)) => {
             dbg!(graph);
         }
-        Err(None) => {}
+        Err(QueryError::StoppedEarly) => {}
+        Err(QueryError::PrefixNotFound(prefix)) => return Err(Error::PrefixNotFound(prefix)),
+        Err(QueryError::UnrungPlaceBell(pb)) => return Err(Error::UnrungPlaceBell(pb)),
+        Err(QueryError::SpillDir(dir, e)) => return Err(Error::SpillDir(dir, e)),
     };
 
     Ok(())
@@ -11,8 +11,9 @@ use bellframe::{
 use itertools::Itertools;
 use log::log;
 use monument::{
+    graph::MAX_REQUIRED_ROWS,
     layout::new::{coursewise, leadwise, SpliceStyle},
-    music::MusicType,
+    music::{CoursingOrderMask, MusicType, Patterns, StrokeSet},
     OptRange, Query,
 };
 use serde::Deserialize;
@@ -20,10 +21,12 @@ use serde::Deserialize;
 use self::{
     calls::{BaseCalls, SpecificCall},
     length::Length,
+    music::MusicPreset,
 };
 use crate::Error;
 
 mod calls;
+mod music;
 
 const METHOD_BALANCE_ALLOWANCE: f32 = 0.1; // By how much the method balance is allowed to vary
 
@@ -39,11 +42,19 @@ pub struct Spec {
     /// Monument won't stop until it generates the `num_comps` best compositions
     #[serde(default = "get_30")]
     num_comps: usize,
+    /// If set, any composition which shares more than this fraction of its links with an
+    /// already-generated composition will be rejected.  Must be between `0.0` and `1.0`
+    max_overlap: Option<f32>,
     /// Monument won't stop until it generates the `num_comps` best compositions
     #[serde(default)]
     splice_style: SpliceStyle,
     /// A [`Row`] which generates the part heads of this composition
     part_head: Option<String>,
+    /// The row from which the composition starts (defaults to rounds)
+    start_row: Option<String>,
+    /// The row at which the composition finishes (defaults to rounds).  Multi-parts must start
+    /// and finish at the same row
+    end_row: Option<String>,
     /// If `true`, generate compositions lead-wise, rather than course-wise.  This is useful for
     /// cases like cyclic comps where no course heads are preserved across parts.
     #[serde(default)]
@@ -75,7 +86,10 @@ pub struct Spec {
     music_file: Option<PathBuf>,
     /// Specification of which classes of music Monument should consider
     #[serde(default)]
-    music: Vec<MusicSpec>,
+    music: MusicSpecs,
+    /// Standard music definitions which should be added to `music`
+    #[serde(default)]
+    music_presets: Vec<MusicPreset>,
     /// The value for `non_duffer` given to music types when none is explicitly given
     #[serde(default = "get_true")]
     default_non_duffer: bool,
@@ -88,6 +102,21 @@ pub struct Spec {
     split_tenors: bool,
     /// Which course heads masks are allowed (overrides `split_tenors`)
     course_heads: Option<Vec<String>>,
+    /// Weights applied to every row (not every course) in courses with particular course heads
+    #[serde(default)]
+    course_head_row_weights: Vec<CourseHeadRowWeightSpec>,
+    /// Bounds on the number of courses with particular course heads.  Can't be used with
+    /// `leadwise`.
+    #[serde(default)]
+    course_head_counts: Vec<CourseHeadCountSpec>,
+    /// Rows which must be included in every composition
+    #[serde(default)]
+    required_rows: Vec<RowPatternSpec>,
+    /// Rows which can't be included in any composition
+    #[serde(default)]
+    forbidden_rows: Vec<RowPatternSpec>,
+    /// A fixed call string with which every composition must start
+    prefix: Option<PrefixSpec>,
 
     /// The [`Method`] who's compositions we are after
     method: Option<MethodSpec>,
@@ -97,6 +126,18 @@ pub struct Spec {
     /// Bounds on how many rows of each method is allowed
     #[serde(default)]
     method_count: OptRange,
+    /// Bounds on how many changes of method are allowed
+    #[serde(default)]
+    changes_of_method: OptRange,
+    /// The weight given to each change of method
+    #[serde(default)]
+    splice_weight: f32,
+    /// If `true`, only generate all-the-work compositions
+    #[serde(default)]
+    atw: bool,
+    /// The weight given to each new place bell rung, to encourage the search towards ATW
+    #[serde(default)]
+    atw_weight: f32,
 }
 
 impl Spec {
@@ -108,6 +149,16 @@ impl Spec {
 
     /// 'Lower' this specification into the information required to build a composition.
     pub fn lower(&self, toml_path: &Path) -> Result<Query, Error> {
+        // Lead-wise layouts only know about lead heads, so every lead would be counted as a course
+        if self.leadwise && !self.course_head_counts.is_empty() {
+            return Err(Error::LeadwiseCourseHeadCounts);
+        }
+        if let Some(max_overlap) = self.max_overlap {
+            if !(0.0..=1.0).contains(&max_overlap) {
+                return Err(Error::InvalidMaxOverlap(max_overlap));
+            }
+        }
+
         // Generate methods
         let mut methods: Vec<(Method, String)> = self
             .methods
@@ -127,11 +178,15 @@ impl Spec {
         let tenor = Bell::tenor(stage);
 
         // Music
-        let mut music_types = self
-            .music
-            .iter()
-            .flat_map(|spec| spec.to_music_types(stage, self.default_non_duffer))
-            .collect_vec();
+        let mut music_types = Vec::new();
+        for spec in &self.music.to_music_specs(stage) {
+            music_types.extend(spec.to_music_types(stage, self.default_non_duffer)?);
+        }
+        for preset in &self.music_presets {
+            for spec in preset.to_music_specs(stage) {
+                music_types.extend(spec.to_music_types(stage, self.default_non_duffer)?);
+            }
+        }
         if let Some(relative_music_path) = &self.music_file {
             // Compute the path of the music TOML file
             let mut music_path = toml_path
@@ -144,18 +199,49 @@ impl Spec {
             let music_file: MusicFile = read_toml(&music_path, &mut toml_buf)
                 .map_err(|toml_err| Error::MusicFile(music_path.to_owned(), toml_err))?;
             // Add the music types
-            music_types.extend(
-                music_file
-                    .music
-                    .iter()
-                    .flat_map(|spec| spec.to_music_types(stage, self.default_non_duffer)),
-            );
+            for spec in &music_file.music {
+                music_types.extend(spec.to_music_types(stage, self.default_non_duffer)?);
+            }
         }
 
         if music_types.is_empty() {
             log::warn!("No music patterns specified.  Are you sure you don't care about music?");
         }
 
+        // CH row weights
+        let course_head_row_weights = self
+            .course_head_row_weights
+            .iter()
+            .flat_map(|spec| spec.masks.iter().map(|s| (Mask::parse(s), spec.weight)))
+            .collect_vec();
+
+        // CH counts
+        let course_head_counts = self
+            .course_head_counts
+            .iter()
+            .map(|spec| {
+                (
+                    spec.masks.iter().map(|s| Mask::parse(s)).collect_vec(),
+                    spec.count,
+                )
+            })
+            .collect_vec();
+
+        // Required/forbidden rows
+        if self.required_rows.len() > MAX_REQUIRED_ROWS {
+            return Err(Error::TooManyRequiredRows(self.required_rows.len()));
+        }
+        let required_rows = self
+            .required_rows
+            .iter()
+            .map(RowPatternSpec::lower)
+            .collect_vec();
+        let forbidden_rows = self
+            .forbidden_rows
+            .iter()
+            .map(RowPatternSpec::lower)
+            .collect_vec();
+
         // CH masks
         let course_head_masks = if let Some(ch_mask_strings) = &self.course_heads {
             // If masks are specified, parse all the course head mask strings into `Mask`s,
@@ -190,6 +276,19 @@ impl Spec {
             Some(ph) => RowBuf::parse_with_stage(ph, stage).map_err(Error::PartHeadParse)?,
             None => RowBuf::rounds(stage),
         };
+        let start_row = match &self.start_row {
+            Some(r) => RowBuf::parse_with_stage(r, stage).map_err(Error::StartRowParse)?,
+            None => RowBuf::rounds(stage),
+        };
+        let end_row = match &self.end_row {
+            Some(r) => RowBuf::parse_with_stage(r, stage).map_err(Error::EndRowParse)?,
+            None => RowBuf::rounds(stage),
+        };
+        // Each part of a multi-part must come back to the row it started from, otherwise the
+        // part heads wouldn't join up
+        if !part_head.is_rounds() && start_row != end_row {
+            return Err(Error::MultiPartStartEndRows);
+        }
 
         // Compute the start/end indices.
         let mut start_indices = if self.snap_start {
@@ -230,7 +329,14 @@ impl Spec {
 
         // Generate a `Layout` from the data about the method and calls
         let layout = if self.leadwise {
-            leadwise::leadwise(&methods, &calls, start_indices, end_indices)
+            leadwise::leadwise(
+                &methods,
+                &calls,
+                start_indices,
+                end_indices,
+                &start_row,
+                &end_row,
+            )
         } else {
             coursewise::coursewise(
                 &methods,
@@ -239,6 +345,8 @@ impl Spec {
                 course_head_masks,
                 start_indices,
                 end_indices,
+                &start_row,
+                &end_row,
             )
         }
         .map_err(Error::LayoutGen)?;
@@ -249,10 +357,20 @@ impl Spec {
             part_head,
             len_range: self.length.range.clone(),
             num_comps: self.num_comps,
+            max_overlap: self.max_overlap,
 
             method_count_range,
+            changes_of_method: self.changes_of_method,
+            splice_weight: self.splice_weight,
+            atw: self.atw,
+            atw_weight: self.atw_weight,
+            course_head_row_weights,
             music_types,
             max_duffer_rows: self.max_duffer_rows,
+            required_rows,
+            forbidden_rows,
+            course_head_counts,
+            comp_prefix: self.prefix.as_ref().map(PrefixSpec::lower),
         })
     }
 }
@@ -409,6 +527,57 @@ impl MethodSpec {
     }
 }
 
+/// A weight applied to every row in a course whose course head matches any of the masks
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CourseHeadRowWeightSpec {
+    masks: Vec<String>,
+    weight: f32,
+}
+
+/// Bounds on how many courses can have course heads matching any of the masks
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CourseHeadCountSpec {
+    masks: Vec<String>,
+    count: OptRange,
+}
+
+/// A pattern of rows (e.g. `13572468` or `*8765`), optionally at a specific stroke
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged, deny_unknown_fields)]
+pub enum RowPatternSpec {
+    Pattern(String),
+    WithStroke { pattern: String, stroke: StrokeSet },
+}
+
+impl RowPatternSpec {
+    fn lower(&self) -> (Regex, StrokeSet) {
+        match self {
+            Self::Pattern(pattern) => (Regex::parse(pattern), StrokeSet::Both),
+            Self::WithStroke { pattern, stroke } => (Regex::parse(pattern), *stroke),
+        }
+    }
+}
+
+/// The start of a composition, either as one call string (e.g. `sHsH`) or as a sequence of calls
+/// which are concatenated (e.g. `["sH", "sH"]`)
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub enum PrefixSpec {
+    CallString(String),
+    Calls(Vec<String>),
+}
+
+impl PrefixSpec {
+    fn lower(&self) -> String {
+        match self {
+            Self::CallString(s) => s.clone(),
+            Self::Calls(calls) => calls.concat(),
+        }
+    }
+}
+
 ///////////
 // MUSIC //
 ///////////
@@ -419,6 +588,30 @@ pub struct MusicFile {
     music: Vec<MusicSpec>,
 }
 
+/// The value of the `music` attribute: either a list of music types or the name of a
+/// [`MusicPreset`] (e.g. `music = "default"`)
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub enum MusicSpecs {
+    Preset(MusicPreset),
+    Specs(Vec<MusicSpec>),
+}
+
+impl MusicSpecs {
+    fn to_music_specs(&self, stage: Stage) -> Vec<MusicSpec> {
+        match self {
+            MusicSpecs::Preset(preset) => preset.to_music_specs(stage),
+            MusicSpecs::Specs(specs) => specs.clone(),
+        }
+    }
+}
+
+impl Default for MusicSpecs {
+    fn default() -> Self {
+        MusicSpecs::Specs(Vec::new())
+    }
+}
+
 /// The specification for one type of music
 // TODO: Find a way to get good deserialization without duplicating fields in every enum variant.
 #[derive(Debug, Clone, Deserialize)]
@@ -431,11 +624,65 @@ pub enum MusicSpec {
         internal: bool,
         #[serde(default = "get_one")]
         weight: f32,
+        /// The weight given to each run length (overriding `weight`).  There must be one weight
+        /// for every length in `run_lengths`
+        #[serde(rename = "run_weights")]
+        weights: Option<Vec<f32>>,
+        /// If `true`, front, back and internal runs are counted as separate music types
+        #[serde(default)]
+        separate_locations: bool,
         /// Possibly unbounded range of counts which are allowed in this music type
         #[serde(default)]
         count: OptRange,
         /// If `true`, then any nodes containing this music will be marked as 'non-duffer'
         non_duffer: Option<bool>,
+        /// Which strokes this music is counted at
+        #[serde(default)]
+        stroke: StrokeSet,
+    },
+    /// Runs which are split across two consecutive rows
+    Wraps {
+        #[serde(rename = "wrap_lengths")]
+        lengths: Vec<usize>,
+        #[serde(default = "get_one")]
+        weight: f32,
+        /// Music counts can't be bounded for multi-row music, so setting this is an error
+        #[serde(default)]
+        count: OptRange,
+        /// If `true`, then any nodes containing this music will be marked as 'non-duffer'
+        non_duffer: Option<bool>,
+        /// Which strokes this music is counted at (i.e. the stroke of the first row)
+        #[serde(default)]
+        stroke: StrokeSet,
+    },
+    /// Masks which match the coursing orders of courses.  These are counted once for every row
+    /// in a matching course.
+    CoursingOrders {
+        coursing_orders: Vec<String>,
+        #[serde(default = "get_one")]
+        weight: f32,
+        /// Possibly unbounded range of counts which are allowed in this music type
+        #[serde(default)]
+        count: OptRange,
+        /// If `true`, then any nodes containing this music will be marked as 'non-duffer'
+        non_duffer: Option<bool>,
+        /// Which strokes this music is counted at
+        #[serde(default)]
+        stroke: StrokeSet,
+    },
+    /// Pairs of patterns which match two consecutive rows
+    RowPairs {
+        row_pairs: Vec<(String, String)>,
+        #[serde(default = "get_one")]
+        weight: f32,
+        /// Music counts can't be bounded for multi-row music, so setting this is an error
+        #[serde(default)]
+        count: OptRange,
+        /// If `true`, then any nodes containing this music will be marked as 'non-duffer'
+        non_duffer: Option<bool>,
+        /// Which strokes this music is counted at (i.e. the stroke of the first row)
+        #[serde(default)]
+        stroke: StrokeSet,
     },
     Patterns {
         patterns: Vec<String>,
@@ -449,32 +696,93 @@ pub enum MusicSpec {
         count: OptRange,
         /// If `true`, then any nodes containing this music will be marked as 'non-duffer'
         non_duffer: Option<bool>,
+        /// Which strokes this music is counted at
+        #[serde(default)]
+        stroke: StrokeSet,
     },
 }
 
 impl MusicSpec {
     /// Generates a [`MusicType`] representing `self`.
-    pub fn to_music_types(&self, stage: Stage, default_non_duffer: bool) -> Vec<MusicType> {
+    pub fn to_music_types(
+        &self,
+        stage: Stage,
+        default_non_duffer: bool,
+    ) -> Result<Vec<MusicType>, Error> {
         enum LoweredType<'_self> {
             /// Equivalent to [`Self::Runs`] or [`Self::RunsList`]
-            Runs(&'_self [usize], &'_self bool),
+            Runs(
+                &'_self [usize],
+                &'_self bool,
+                &'_self Option<Vec<f32>>,
+                &'_self bool,
+            ),
             /// Equivalent to [`Self::Pattern`] or [`Self::Pattern`]
             Patterns(&'_self [String], &'_self OptRange),
+            /// Equivalent to [`Self::Wraps`]
+            Wraps(&'_self [usize]),
+            /// Equivalent to [`Self::RowPairs`]
+            RowPairs(&'_self [(String, String)]),
+            /// Equivalent to [`Self::CoursingOrders`]
+            CoursingOrders(&'_self [String]),
         }
 
         // Extract the information from `self` into a normalised form
-        let (lowered_type, weight, count, non_duffer) = match self {
+        let (lowered_type, weight, count, non_duffer, stroke) = match self {
             Self::Runs {
                 lengths,
                 internal,
                 weight,
+                weights,
+                separate_locations,
+                count,
+                non_duffer,
+                stroke,
+            } => (
+                LoweredType::Runs(lengths, internal, weights, separate_locations),
+                weight,
+                count,
+                non_duffer,
+                stroke,
+            ),
+            Self::Wraps {
+                lengths,
+                weight,
+                count,
+                non_duffer,
+                stroke,
+            } => (
+                LoweredType::Wraps(lengths),
+                weight,
+                count,
+                non_duffer,
+                stroke,
+            ),
+            Self::CoursingOrders {
+                coursing_orders,
+                weight,
                 count,
                 non_duffer,
+                stroke,
             } => (
-                LoweredType::Runs(lengths, internal),
+                LoweredType::CoursingOrders(coursing_orders),
                 weight,
                 count,
                 non_duffer,
+                stroke,
+            ),
+            Self::RowPairs {
+                row_pairs,
+                weight,
+                count,
+                non_duffer,
+                stroke,
+            } => (
+                LoweredType::RowPairs(row_pairs),
+                weight,
+                count,
+                non_duffer,
+                stroke,
             ),
             Self::Patterns {
                 patterns,
@@ -482,26 +790,131 @@ impl MusicSpec {
                 weight,
                 count,
                 non_duffer,
+                stroke,
             } => (
                 LoweredType::Patterns(patterns, count_each),
                 weight,
                 count,
                 non_duffer,
+                stroke,
             ),
         };
         let weight = *weight;
         let count = *count;
+        let stroke = *stroke;
         let non_duffer = non_duffer.unwrap_or(default_non_duffer);
 
-        match lowered_type {
-            LoweredType::Runs(lengths, internal) => {
-                let regexes = lengths
+        Ok(match lowered_type {
+            LoweredType::Runs(lengths, internal, weights, separate_locations) => {
+                if count.is_set() {
+                    if weights.is_some() || *separate_locations {
+                        return Err(Error::RunCountSplit);
+                    }
+                    // If `count` is set, then it applies to all the runs so they must be grouped
+                    // into one `MusicType`
+                    let regexes = lengths
+                        .iter()
+                        .flat_map(|length| Regex::runs(stage, *length, *internal))
+                        .collect_vec();
+                    return Ok(vec![MusicType::new(
+                        Patterns::Rows(regexes),
+                        weight,
+                        count,
+                        non_duffer,
+                        stroke,
+                    )]);
+                }
+
+                let weights = match weights {
+                    Some(weights) if weights.len() != lengths.len() => {
+                        return Err(Error::RunWeightCount {
+                            num_lengths: lengths.len(),
+                            num_weights: weights.len(),
+                        })
+                    }
+                    Some(weights) => weights.clone(),
+                    None => vec![weight; lengths.len()],
+                };
+                // Otherwise, every run length gets a separate `MusicType` (and so is given a
+                // separate count in the music breakdown)
+                let mut music_types = Vec::new();
+                for (&length, &weight) in lengths.iter().zip_eq(&weights) {
+                    let regex_groups = if *separate_locations {
+                        let mut locations = vec![RunLocation::Front, RunLocation::Back];
+                        if *internal {
+                            locations.push(RunLocation::Internal);
+                        }
+                        locations
+                            .into_iter()
+                            .map(|loc| run_regexes(stage, length, loc))
+                            .filter(|regexes| !regexes.is_empty())
+                            .collect_vec()
+                    } else {
+                        vec![Regex::runs(stage, length, *internal)]
+                    };
+                    for regexes in regex_groups {
+                        music_types.push(MusicType::new(
+                            Patterns::Rows(regexes),
+                            weight,
+                            count,
+                            non_duffer,
+                            stroke,
+                        ));
+                    }
+                }
+                music_types
+            }
+            LoweredType::Wraps(lengths) => {
+                if count.is_set() {
+                    return Err(Error::MultiRowCount);
+                }
+                // Like runs, all the lengths are grouped into one `MusicType`
+                let pairs = lengths
                     .iter()
-                    .flat_map(|length| Regex::runs(stage, *length, *internal))
+                    .flat_map(|length| Patterns::wrapped_runs(stage, *length))
                     .collect_vec();
-                // Runs can't take the `count_each` parameter, so can all be grouped into one
-                // `MusicType`
-                vec![MusicType::new(regexes, weight, count, non_duffer)]
+                vec![MusicType::new(
+                    Patterns::RowPairs(pairs),
+                    weight,
+                    count,
+                    non_duffer,
+                    stroke,
+                )]
+            }
+            LoweredType::RowPairs(row_pairs) => {
+                if count.is_set() {
+                    return Err(Error::MultiRowCount);
+                }
+                let pairs = row_pairs
+                    .iter()
+                    .map(|(first, second)| (Regex::parse(first), Regex::parse(second)))
+                    .collect_vec();
+                vec![MusicType::new(
+                    Patterns::RowPairs(pairs),
+                    weight,
+                    count,
+                    non_duffer,
+                    stroke,
+                )]
+            }
+            LoweredType::CoursingOrders(coursing_orders) => {
+                // Coursing orders don't include the treble or the tenor
+                let co_len = stage.num_bells() - 2;
+                let masks = coursing_orders
+                    .iter()
+                    .map(|s| {
+                        CoursingOrderMask::parse(s)
+                            .filter(|mask| mask.len() == co_len)
+                            .ok_or_else(|| Error::InvalidCoursingOrder(s.to_owned()))
+                    })
+                    .collect::<Result<Vec<_>, _>>()?;
+                vec![MusicType::new(
+                    Patterns::CoursingOrders(masks),
+                    weight,
+                    count,
+                    non_duffer,
+                    stroke,
+                )]
             }
             LoweredType::Patterns(patterns, count_each) => {
                 let regexes = patterns.iter().map(|s| Regex::parse(s));
@@ -513,21 +926,76 @@ impl MusicSpec {
                     // pattern.  Each `MusicType` will contain exactly one `regex` corresponding to
                     // that pattern.
                     regexes
-                        .map(|regex| MusicType::new(vec![regex], weight, *count_each, non_duffer))
+                        .map(|regex| {
+                            let patterns = Patterns::Rows(vec![regex]);
+                            MusicType::new(patterns, weight, *count_each, non_duffer, stroke)
+                        })
                         .collect_vec()
                 } else {
                     // If `count_each` isn't set, we group all the patterns into one `MusicType` and
                     // apply `count` to all the regexes
                     vec![MusicType::new(
-                        regexes.collect_vec(),
+                        Patterns::Rows(regexes.collect_vec()),
                         weight,
                         count,
                         non_duffer,
+                        stroke,
                     )]
                 }
             }
+        })
+    }
+}
+
+/// Where in a row a run can occur
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum RunLocation {
+    Front,
+    Back,
+    Internal,
+}
+
+/// Generate the [`Regex`]es which match (ascending or descending) runs of exactly `length` bells
+/// in a given location within rows.
+fn run_regexes(stage: Stage, length: usize, location: RunLocation) -> Vec<Regex> {
+    let num_bells = stage.num_bells();
+    if length > num_bells {
+        return vec![];
+    }
+    // Runs covering the whole row are both at the front and the back; we only count them at the
+    // front
+    if length == num_bells && location == RunLocation::Back {
+        return vec![];
+    }
+
+    let mut regexes = Vec::new();
+    for first_bell in 0..=num_bells - length {
+        let ascending = (first_bell..first_bell + length)
+            .map(Bell::from_index)
+            .collect_vec();
+        let descending = ascending.iter().rev().copied().collect_vec();
+        for run in [ascending, descending] {
+            let run = run.iter().join("");
+            match location {
+                RunLocation::Front => regexes.push(Regex::parse(&format!("{}*", run))),
+                RunLocation::Back => regexes.push(Regex::parse(&format!("*{}", run))),
+                // Internal runs have at least one bell on either side
+                RunLocation::Internal => {
+                    for bells_before in 1..num_bells.saturating_sub(length) {
+                        let bells_after = num_bells - length - bells_before;
+                        let pattern = format!(
+                            "{}{}{}",
+                            "x".repeat(bells_before),
+                            run,
+                            "x".repeat(bells_after)
+                        );
+                        regexes.push(Regex::parse(&pattern));
+                    }
+                }
+            }
         }
     }
+    regexes
 }
 
 /* Deserialization helpers */
@@ -713,3 +1181,148 @@ mod length {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use bellframe::{RowBuf, Stage};
+    use monument::Query;
+
+    use super::{run_regexes, RunLocation, Spec};
+    use crate::Error;
+
+    /// A `method` definition which doesn't need the CC method library
+    const PLAIN_BOB_MAJOR: &str =
+        r#"method = { name = "Plain Bob", place_notation = "x18x18x18x18,12", stage = 8 }"#;
+
+    /// Parse and lower a spec given as a TOML string
+    fn lower(toml: &str) -> Result<Query, Error> {
+        let spec: Spec = toml::from_str(toml).unwrap();
+        spec.lower(Path::new("spec.toml"))
+    }
+
+    #[test]
+    fn leadwise_course_head_counts() {
+        let course_head_counts =
+            r#"course_head_counts = [{ masks = ["xxxxxx78"], count = { max = 1 } }]"#;
+        let spec = format!(
+            "length = 'practice'\n{}\n{}",
+            PLAIN_BOB_MAJOR, course_head_counts
+        );
+        assert!(lower(&spec).is_ok());
+        let leadwise_spec = format!("leadwise = true\n{}", spec);
+        assert!(matches!(
+            lower(&leadwise_spec),
+            Err(Error::LeadwiseCourseHeadCounts)
+        ));
+    }
+
+    #[test]
+    fn music_preset_shorthand() {
+        let num_music_types = |music: &str| {
+            let spec = format!("length = 'practice'\n{}\n{}", music, PLAIN_BOB_MAJOR);
+            lower(&spec).unwrap().music_types.len()
+        };
+        let num_default_types = num_music_types("music_presets = ['default']");
+        assert!(num_default_types > 0);
+        assert_eq!(num_music_types("music = 'default'"), num_default_types);
+        assert_eq!(num_music_types("music = []"), 0);
+    }
+
+    #[test]
+    fn multi_part_start_end_rows() {
+        let spec = |rows: &str| {
+            format!(
+                "length = 'practice'\npart_head = '13425678'\n{}\n{}",
+                rows, PLAIN_BOB_MAJOR
+            )
+        };
+        assert!(lower(&spec("start_row = '12345678'")).is_ok());
+        assert!(matches!(
+            lower(&spec("end_row = '13245678'")),
+            Err(Error::MultiPartStartEndRows)
+        ));
+    }
+
+    #[test]
+    fn max_overlap_bounds() {
+        let lower_overlap = |max_overlap: &str| {
+            let spec = format!(
+                "length = 'practice'\nmax_overlap = {}\n{}",
+                max_overlap, PLAIN_BOB_MAJOR
+            );
+            lower(&spec)
+        };
+        assert!(lower_overlap("0.0").is_ok());
+        assert!(lower_overlap("0.8").is_ok());
+        assert!(lower_overlap("1.0").is_ok());
+        for &invalid in &["-0.1", "1.5", "80.0"] {
+            assert!(matches!(
+                lower_overlap(invalid),
+                Err(Error::InvalidMaxOverlap(_))
+            ));
+        }
+    }
+
+    /// The number of regexes generated by `run_regexes` which match `row`
+    fn num_matches(length: usize, location: RunLocation, row: &str) -> usize {
+        let row = RowBuf::parse_with_stage(row, Stage::MAJOR).unwrap();
+        run_regexes(Stage::MAJOR, length, location)
+            .iter()
+            .filter(|regex| regex.matches(&row))
+            .count()
+    }
+
+    #[test]
+    fn run_regex_counts() {
+        use RunLocation::*;
+        #[rustfmt::skip]
+        let cases = &[
+            (4, Front, 10),
+            (4, Back, 10),
+            (4, Internal, 30),
+            // Runs of `num_bells - 1` can't be internal
+            (7, Front, 4),
+            (7, Back, 4),
+            (7, Internal, 0),
+            // Runs covering the whole row are only counted at the front
+            (8, Front, 2),
+            (8, Back, 0),
+            (8, Internal, 0),
+            (9, Front, 0),
+        ];
+
+        for &(length, location, exp_num_regexes) in cases {
+            let num_regexes = run_regexes(Stage::MAJOR, length, location).len();
+            assert_eq!(num_regexes, exp_num_regexes, "{} {:?}", length, location);
+        }
+    }
+
+    #[test]
+    fn run_regex_matches() {
+        use RunLocation::*;
+        #[rustfmt::skip]
+        let cases = &[
+            (4, "12345678", [1, 1, 3]),
+            (4, "56781234", [1, 1, 0]),
+            (4, "81234765", [0, 0, 1]),
+            (4, "13572468", [0, 0, 0]),
+            (7, "12345678", [1, 1, 0]),
+            (7, "81234567", [0, 1, 0]),
+            (8, "12345678", [1, 0, 0]),
+            (8, "87654321", [1, 0, 0]),
+        ];
+
+        for (length, row, exp_matches) in cases {
+            for (location, exp_num_matches) in [Front, Back, Internal].iter().zip(exp_matches) {
+                let num_matches = num_matches(*length, *location, row);
+                assert_eq!(
+                    num_matches, *exp_num_matches,
+                    "{} {} {:?}",
+                    length, row, location
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,278 @@
+use bellframe::{Bell, Stage};
+use itertools::Itertools;
+use monument::{music::StrokeSet, OptRange};
+use serde::Deserialize;
+
+use super::MusicSpec;
+
+/// The values of the `music_presets` attribute: standard music definitions which can be generated
+/// for any [`Stage`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum MusicPreset {
+    /// Equivalent to `runs`, `named-rows` and `rollups`
+    Default,
+    /// Runs of 4 or more bells, at the front or back of rows
+    Runs,
+    /// Queens, kings, tittums and (on 8 bells) whittingtons
+    NamedRows,
+    /// Combination roll-ups (e.g. `4578`, `6478` at the back of rows), except those which are
+    /// already `rollups`
+    Crus,
+    /// Rows which are one swap away from rounds
+    NearMisses,
+    /// Rows which are two swaps away from rounds (e.g. `21345687`)
+    CombinationNearMisses,
+    /// `5678`, `6578` and `8765` (or their equivalents on other stages) at the front or back of
+    /// rows
+    Rollups,
+}
+
+impl MusicPreset {
+    pub fn to_music_specs(self, stage: Stage) -> Vec<MusicSpec> {
+        let num_bells = stage.num_bells();
+        match self {
+            MusicPreset::Default => [Self::Runs, Self::NamedRows, Self::Rollups]
+                .iter()
+                .flat_map(|preset| preset.to_music_specs(stage))
+                .collect_vec(),
+            MusicPreset::Runs => {
+                if num_bells < 4 {
+                    return vec![];
+                }
+                vec![MusicSpec::Runs {
+                    lengths: (4..=num_bells).collect_vec(),
+                    internal: false,
+                    weight: 1.0,
+                    weights: None,
+                    separate_locations: false,
+                    count: OptRange::default(),
+                    non_duffer: None,
+                    stroke: StrokeSet::Both,
+                }]
+            }
+            MusicPreset::NamedRows => named_rows(num_bells)
+                .into_iter()
+                .map(|row| patterns(vec![row]))
+                .collect_vec(),
+            MusicPreset::Crus => {
+                if num_bells < 7 {
+                    return vec![];
+                }
+                // Any two of the bells `4..=n-2` followed by the two back bells, except `5678` and
+                // `6578` (which are rollups)
+                let back_bells = [num_bells - 1, num_bells];
+                let cru_bells = 4..=num_bells - 2;
+                let rollup_bells = [num_bells - 3, num_bells - 2];
+                let pats = cru_bells
+                    .clone()
+                    .cartesian_product(cru_bells)
+                    .filter(|(a, b)| {
+                        a != b && !(rollup_bells.contains(a) && rollup_bells.contains(b))
+                    })
+                    .map(|(a, b)| format!("*{}", bell_names([a, b].iter().chain(&back_bells))))
+                    .collect_vec();
+                if pats.is_empty() {
+                    return vec![];
+                }
+                vec![patterns(pats)]
+            }
+            MusicPreset::NearMisses => {
+                // Swap each adjacent pair of bells in rounds
+                let pats = (1..num_bells)
+                    .map(|swap| {
+                        let mut bells = (1..=num_bells).collect_vec();
+                        bells.swap(swap - 1, swap);
+                        bell_names(&bells)
+                    })
+                    .collect_vec();
+                vec![patterns(pats)]
+            }
+            MusicPreset::CombinationNearMisses => {
+                // Swap two adjacent pairs of bells in rounds, where the pairs don't overlap
+                let pats = (1..num_bells)
+                    .tuple_combinations()
+                    .filter(|(swap1, swap2)| swap2 - swap1 >= 2)
+                    .map(|(swap1, swap2)| {
+                        let mut bells = (1..=num_bells).collect_vec();
+                        bells.swap(swap1 - 1, swap1);
+                        bells.swap(swap2 - 1, swap2);
+                        bell_names(&bells)
+                    })
+                    .collect_vec();
+                if pats.is_empty() {
+                    return vec![];
+                }
+                vec![patterns(pats)]
+            }
+            MusicPreset::Rollups => {
+                if num_bells < 4 {
+                    return vec![];
+                }
+                let n = num_bells;
+                let rollups = [
+                    [n - 3, n - 2, n - 1, n],
+                    [n - 2, n - 3, n - 1, n],
+                    [n, n - 1, n - 2, n - 3],
+                ];
+                let pats = rollups
+                    .iter()
+                    .flat_map(|bells| {
+                        let names = bell_names(bells);
+                        [format!("{}*", names), format!("*{}", names)]
+                    })
+                    .collect_vec();
+                vec![patterns(pats)]
+            }
+        }
+    }
+}
+
+/// The named rows which exist on a given number of bells, as strings.  On odd stages, the rows are
+/// generated on the even number of bells below and the last bell is kept at the back.
+fn named_rows(num_bells: usize) -> Vec<String> {
+    let working_bells = num_bells - num_bells % 2;
+    if working_bells < 6 {
+        return vec![];
+    }
+    let odds = (1..=working_bells).step_by(2).collect_vec();
+    let evens = (2..=working_bells).step_by(2).collect_vec();
+    let half = working_bells / 2;
+
+    let queens = odds.iter().chain(&evens).copied().collect_vec();
+    let kings = odds.iter().rev().chain(&evens).copied().collect_vec();
+    let tittums = (1..=half).flat_map(|b| [b, b + half]).collect_vec();
+    let mut rows = vec![queens, kings, tittums];
+    if working_bells == 8 {
+        rows.push(vec![1, 2, 7, 5, 3, 4, 6, 8]); // Whittingtons
+    }
+
+    rows.into_iter()
+        .map(|mut row| {
+            row.extend(working_bells + 1..=num_bells); // Add the cover on odd stages
+            bell_names(&row)
+        })
+        .collect_vec()
+}
+
+/// Convert a sequence of 1-indexed bell numbers into a string of bell names
+fn bell_names<'a>(bells: impl IntoIterator<Item = &'a usize>) -> String {
+    bells.into_iter().map(|b| Bell::from_index(b - 1)).join("")
+}
+
+/// Create a [`MusicSpec`] which groups a set of patterns into one music type
+fn patterns(patterns: Vec<String>) -> MusicSpec {
+    MusicSpec::Patterns {
+        patterns,
+        count_each: OptRange::default(),
+        weight: 1.0,
+        count: OptRange::default(),
+        non_duffer: None,
+        stroke: StrokeSet::Both,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use bellframe::Stage;
+    use itertools::Itertools;
+
+    use super::MusicPreset;
+    use crate::spec::MusicSpec;
+
+    /// The patterns generated by a preset which only generates patterns
+    fn pattern_strings(preset: MusicPreset, stage: Stage) -> Vec<String> {
+        preset
+            .to_music_specs(stage)
+            .into_iter()
+            .flat_map(|spec| match spec {
+                MusicSpec::Patterns { patterns, .. } => patterns,
+                _ => panic!("{:?} doesn't generate patterns", preset),
+            })
+            .collect_vec()
+    }
+
+    #[test]
+    fn named_rows() {
+        let named_rows = |stage| pattern_strings(MusicPreset::NamedRows, stage);
+        assert_eq!(named_rows(Stage::MINOR), ["135246", "531246", "142536"]);
+        assert_eq!(
+            named_rows(Stage::TRIPLES),
+            ["1352467", "5312467", "1425367"]
+        );
+        assert_eq!(
+            named_rows(Stage::MAJOR),
+            ["13572468", "75312468", "15263748", "12753468"]
+        );
+        assert_eq!(
+            named_rows(Stage::MAXIMUS),
+            ["13579E24680T", "E9753124680T", "172839405E6T"]
+        );
+    }
+
+    #[test]
+    fn crus() {
+        let crus = |stage| pattern_strings(MusicPreset::Crus, stage);
+        assert!(crus(Stage::MINOR).is_empty());
+        assert!(crus(Stage::TRIPLES).is_empty());
+        assert_eq!(crus(Stage::MAJOR), ["*4578", "*4678", "*5478", "*6478"]);
+        assert_eq!(crus(Stage::MAXIMUS).len(), 7 * 6 - 2);
+    }
+
+    #[test]
+    fn near_misses() {
+        let near_misses = |stage| pattern_strings(MusicPreset::NearMisses, stage);
+        assert_eq!(
+            near_misses(Stage::MINOR),
+            ["213456", "132456", "124356", "123546", "123465"]
+        );
+        assert_eq!(near_misses(Stage::TRIPLES).len(), 6);
+        assert_eq!(near_misses(Stage::MAJOR).len(), 7);
+        assert_eq!(near_misses(Stage::MAXIMUS).len(), 11);
+
+        let cnms = |stage| pattern_strings(MusicPreset::CombinationNearMisses, stage);
+        assert_eq!(
+            cnms(Stage::MINOR),
+            ["214356", "213546", "213465", "132546", "132465", "124365"]
+        );
+        // Choosing 2 non-overlapping pairs from the `n - 1` adjacent pairs
+        assert_eq!(cnms(Stage::TRIPLES).len(), 5 * 4 / 2);
+        assert_eq!(cnms(Stage::MAJOR).len(), 6 * 5 / 2);
+        assert_eq!(cnms(Stage::MAXIMUS).len(), 10 * 9 / 2);
+    }
+
+    #[test]
+    fn rollups() {
+        let rollups = |stage| pattern_strings(MusicPreset::Rollups, stage);
+        assert_eq!(
+            rollups(Stage::MINOR),
+            ["3456*", "*3456", "4356*", "*4356", "6543*", "*6543"]
+        );
+        assert_eq!(
+            rollups(Stage::MAJOR),
+            ["5678*", "*5678", "6578*", "*6578", "8765*", "*8765"]
+        );
+        assert_eq!(rollups(Stage::MAXIMUS)[..2], ["90ET*", "*90ET"]);
+    }
+
+    #[test]
+    fn no_duplicate_patterns() {
+        let patterns = [
+            MusicPreset::NamedRows,
+            MusicPreset::Crus,
+            MusicPreset::NearMisses,
+            MusicPreset::CombinationNearMisses,
+            MusicPreset::Rollups,
+        ];
+        for &stage in &[Stage::MINOR, Stage::TRIPLES, Stage::MAJOR, Stage::MAXIMUS] {
+            let all_patterns = patterns
+                .iter()
+                .flat_map(|&preset| pattern_strings(preset, stage))
+                .collect_vec();
+            let unique_patterns = all_patterns.iter().collect::<HashSet<_>>();
+            assert_eq!(all_patterns.len(), unique_patterns.len(), "{:?}", stage);
+        }
+    }
+}
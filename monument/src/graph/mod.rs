@@ -3,31 +3,37 @@
 
 mod falseness;
 pub mod optimise;
+mod prefix;
 
 use std::{
     cmp::{Ordering, Reverse},
     collections::{BinaryHeap, HashMap, HashSet},
 };
 
-use bellframe::{Row, RowBuf};
+use bellframe::{Bell, Row, RowBuf, Stage};
 use itertools::Itertools;
 use log::log;
 
 use crate::{
     layout::{
         node_range::{End, NodeRange, PerPartLength, RangeEnd, RangeFactory, TotalLength},
-        Layout, LinkIdx, NodeId, Rotation, RowRange, StandardNodeId, StartIdx,
+        BlockIdx, Layout, LinkIdx, NodeId, Rotation, RowRange, StandardNodeId, StartIdx,
     },
-    music::{Breakdown, MusicType, Score},
+    music::{Breakdown, MusicType, PerStroke, Score, Stroke},
     utils::{FrontierItem, RowCounts},
     Query,
 };
 
 use self::{falseness::FalsenessTable, optimise::Pass};
 
+pub use self::prefix::FixedPrefix;
+
 /// The number of rows required to get from a point in the graph to a start/end.
 type Distance = usize;
 
+/// The maximum number of required rows, so that they can be stored as a bitmask in a `u64`
+pub const MAX_REQUIRED_ROWS: usize = 64;
+
 /// A 'prototype' node graph that is (relatively) inefficient to traverse but easy to modify.  This
 /// is usually used to build and optimise the node graph before being converted into an efficient
 /// graph representation for use in tree search.
@@ -45,6 +51,9 @@ pub struct Graph {
     end_nodes: Vec<(NodeId, End)>,
     /// The number of different parts
     num_parts: Rotation,
+    /// Every [`PlaceBell`] of every method in the [`Layout`] (see [`all_place_bells`]).  A
+    /// composition is all-the-work if it rings all of these.
+    place_bells: Vec<PlaceBell>,
 }
 
 /// A `Node` in a node [`Graph`].  This is an indivisible chunk of ringing which cannot be split up
@@ -76,8 +85,24 @@ pub struct Node {
     total_length: TotalLength,
     /// The number of rows of each method generated by this node
     method_counts: RowCounts,
-    /// The music generated by this node in the composition.  Optimisation passes can't change this
-    music: Breakdown,
+    /// The music generated by this node in the composition, depending on the [`Stroke`] of this
+    /// node's first row.  Optimisation passes can't change this
+    music: PerStroke<Breakdown>,
+    /// Score generated by this node which doesn't come from music (e.g. course head weights).
+    /// Optimisation passes can't change this
+    extra_score: Score,
+    /// The [`PlaceBell`]s rung by this node, across all parts.  Optimisation passes can't change
+    /// this
+    place_bells: Vec<PlaceBell>,
+    /// `true` if this node contains a forbidden row, depending on the [`Stroke`] of its first row.
+    /// Optimisation passes can't change this
+    forbidden: PerStroke<bool>,
+    /// Bitmask of which of [`Query::required_rows`] are contained in this node, depending on the
+    /// [`Stroke`] of its first row.  Optimisation passes can't change this
+    required_rows: PerStroke<u64>,
+    /// For each of [`Query::course_head_counts`], the number of parts in which this node's course
+    /// head matches one of the masks.  Optimisation passes can't change this
+    course_head_matches: Vec<usize>,
 
     /// `true` if this node doesn't contain music that's interesting to the composition
     duffer: bool,
@@ -97,6 +122,13 @@ pub struct Node {
     /// A lower bound on the number of rows required to go from the first row **after** `self` to
     /// rounds.
     pub lb_distance_to_rounds: Distance,
+    /// An upper bound on the score per row generated by any node (including the link leading to
+    /// it) which can be rung after `self`.  0-length nodes are ignored, because they don't
+    /// contain any rows.
+    pub ub_score_per_row_to_rounds: f32,
+    /// An estimate of the score per row generated by the rows between `self` and rounds, or
+    /// `None` if no estimate has been computed
+    pub est_score_per_row_to_rounds: Option<f32>,
 }
 
 #[derive(Debug, Clone, Eq, PartialEq, Hash)]
@@ -105,18 +137,63 @@ pub struct Link {
     /// Indexes into `Layout::links`
     pub source_idx: LinkIdx,
     pub rotation: Rotation,
+    /// The music generated by the join between the last row before this `Link` and the first row
+    /// after it (e.g. wrapped runs), across all parts.  This is indexed by the [`Stroke`] of the
+    /// first row **after** the `Link`.
+    pub music: PerStroke<Breakdown>,
 }
 
 impl Link {
-    pub fn new(id: NodeId, source_idx: LinkIdx, rotation: Rotation) -> Self {
+    pub fn new(
+        id: NodeId,
+        source_idx: LinkIdx,
+        rotation: Rotation,
+        music: PerStroke<Breakdown>,
+    ) -> Self {
         Self {
             id,
             source_idx,
             rotation,
+            music,
+        }
+    }
+
+    /// Returns `true` if taking this `Link` counts as a change of method.  Splicing just before
+    /// coming round doesn't count.  This is only meaningful for successor links.
+    pub fn is_splice(&self, layout: &Layout) -> bool {
+        layout.links[self.source_idx].is_splice() && self.id.is_standard()
+    }
+
+    /// The score generated by taking this `Link` (summed over all the parts), indexed by the
+    /// [`Stroke`] of the first row after it.  This is only meaningful for successor links.
+    pub fn score(&self, query: &Query, num_parts: Rotation) -> PerStroke<Score> {
+        let weight = self.weight(query, num_parts);
+        // Music across the join has already been summed over all the parts
+        self.music.map(|music| music.score + weight)
+    }
+
+    /// The score generated by taking this `Link` which doesn't come from music (i.e. call and
+    /// splice weights), summed over all the parts.  This is only meaningful for successor links.
+    pub fn weight(&self, query: &Query, num_parts: Rotation) -> f32 {
+        let mut weight = query.layout.links[self.source_idx].weight;
+        if self.is_splice(&query.layout) {
+            weight += query.splice_weight;
         }
+        weight * num_parts as f32
     }
 }
 
+/// A [`Bell`] ringing a place bell of a method (i.e. starting a lead of that method in a given
+/// place).  These are used to determine whether or not a composition is all-the-work.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct PlaceBell {
+    /// The block (i.e. method) being rung
+    pub block: BlockIdx,
+    pub bell: Bell,
+    /// The 0-indexed place of `bell` in the lead head
+    pub place: usize,
+}
+
 // ------------------------------------------------------------------------------------------
 
 impl Graph {
@@ -172,6 +249,25 @@ impl Graph {
     pub fn num_parts(&self) -> Rotation {
         self.num_parts
     }
+
+    /// Every [`PlaceBell`] which must be rung for a composition to be all-the-work
+    pub fn place_bells(&self) -> &[PlaceBell] {
+        &self.place_bells
+    }
+
+    /// Returns a [`PlaceBell`] which isn't rung in full by any node, if one exists.  If this
+    /// returns `Some`, then no composition can be all-the-work.
+    pub fn unrung_place_bell(&self) -> Option<PlaceBell> {
+        let rung = self
+            .nodes
+            .values()
+            .flat_map(|node| node.place_bells.iter())
+            .collect::<HashSet<_>>();
+        self.place_bells
+            .iter()
+            .find(|pb| !rung.contains(pb))
+            .copied()
+    }
 }
 
 // ------------------------------------------------------------------------------------------
@@ -329,22 +425,46 @@ impl Node {
         &self.method_counts
     }
 
-    pub fn score(&self) -> Score {
-        self.music.score
+    /// The score of this node, depending on the [`Stroke`] of its first row
+    pub fn score(&self) -> PerStroke<Score> {
+        self.music
+            .map(|breakdown| breakdown.score + self.extra_score)
     }
 
     pub fn label(&self) -> &str {
         self.label.as_str()
     }
 
-    pub fn music(&self) -> &Breakdown {
+    pub fn music(&self) -> &PerStroke<Breakdown> {
         &self.music
     }
 
+    /// An upper bound on the music generated by this node, regardless of which [`Stroke`] it
+    /// starts at
+    pub fn max_music(&self) -> Breakdown {
+        self.music.hand.pointwise_max(&self.music.back)
+    }
+
     pub fn duffer(&self) -> bool {
         self.duffer
     }
 
+    pub fn forbidden(&self) -> PerStroke<bool> {
+        self.forbidden
+    }
+
+    pub fn required_rows(&self) -> PerStroke<u64> {
+        self.required_rows
+    }
+
+    pub fn course_head_matches(&self) -> &[usize] {
+        &self.course_head_matches
+    }
+
+    pub fn place_bells(&self) -> &[PlaceBell] {
+        &self.place_bells
+    }
+
     // STARTS/ENDS //
 
     pub fn is_start(&self) -> bool {
@@ -391,16 +511,21 @@ impl Node {
 ////////////////////////////////
 
 impl Graph {
-    /// Generate a graph of all nodes which are reachable within a given length constraint.
-    pub fn from_layout(
-        layout: &Layout,
-        music_types: &[MusicType],
-        max_length: usize,
-        part_head: &Row,
-    ) -> Self {
+    /// Generate a graph of all nodes which are reachable within the length constraint of a
+    /// [`Query`].
+    pub fn from_query(query: &Query) -> Self {
+        assert!(
+            query.required_rows.len() <= MAX_REQUIRED_ROWS,
+            "Can't have more than {} required rows",
+            MAX_REQUIRED_ROWS
+        );
+        let layout = &query.layout;
+        // `- 1` makes sure that the length limit is an **inclusive** bound
+        let max_length = query.len_range.end - 1;
+
         // Build the shape of the graph using Dijkstra's algorithm
         let (expanded_node_ranges, start_nodes, end_nodes, ch_equiv_map, part_heads) =
-            build_graph(layout, max_length, part_head);
+            build_graph(layout, max_length, &query.part_head);
         let num_parts = part_heads.len() as Rotation;
 
         // Convert each `expanded_node_range` into a full `Node`, albeit without
@@ -409,10 +534,12 @@ impl Graph {
             .iter()
             .map(|(node_id, (node_range, distance))| {
                 assert_eq!(node_id, &node_range.node_id);
-                let new_node = build_node(node_range, *distance, layout, music_types, &part_heads);
+                let new_node = build_node(node_range, *distance, query, &part_heads);
                 (node_id.clone(), new_node)
             })
             .collect();
+        // Nodes which contain forbidden rows regardless of stroke can never be used
+        nodes.retain(|_id, node| !(node.forbidden.hand && node.forbidden.back));
 
         let plural = |count: usize, singular: &str| -> String {
             let extension = if count == 1 { "" } else { "s" };
@@ -427,10 +554,17 @@ impl Graph {
 
         compute_falseness(&mut nodes, layout, &ch_equiv_map);
 
+        let place_bells = all_place_bells(layout);
+
         // Add predecessor references (every node is a predecessor to all of its successors)
         log::debug!("Setting predecessor links");
         for (id, _dist) in expanded_node_ranges {
-            for succ_link in nodes.get(&id).unwrap().successors.clone() {
+            // Nodes can be missing if they contained forbidden rows
+            let succ_links = match nodes.get(&id) {
+                Some(node) => node.successors.clone(),
+                None => continue,
+            };
+            for succ_link in succ_links {
                 if let Some(node) = nodes.get_mut(&succ_link.id) {
                     assert!(succ_link.rotation < num_parts);
                     node.predecessors.push(Link {
@@ -439,6 +573,7 @@ impl Graph {
                         // Passing backwards over a link gives it the opposite rotation to
                         // traversing forward
                         rotation: num_parts - succ_link.rotation,
+                        music: succ_link.music,
                     });
                 }
             }
@@ -449,6 +584,7 @@ impl Graph {
             start_nodes,
             end_nodes,
             num_parts,
+            place_bells,
         }
     }
 }
@@ -583,26 +719,69 @@ fn build_graph(
 fn build_node(
     node_range: &NodeRange,
     distance: usize,
-    layout: &Layout,
-    music_types: &[MusicType],
+    query: &Query,
     part_heads: &[RowBuf],
 ) -> Node {
-    // Add up music from each part
-    let mut music = Breakdown::zero(music_types.len());
-    for ph in part_heads {
-        if let Some(source_ch) = node_range.node_id.course_head() {
-            let ch = ph * source_ch;
-            music += &Breakdown::from_rows(node_range.untransposed_rows(layout), &ch, music_types);
+    let layout = &query.layout;
+    let music_types = query.music_types.as_slice();
+    // Add up music from each part, for each possible stroke of the node's first row
+    let music = PerStroke::from_fn(|start_stroke: Stroke| {
+        let mut music = Breakdown::zero(music_types.len());
+        for ph in part_heads {
+            if let Some(source_ch) = node_range.node_id.course_head() {
+                let ch = ph * source_ch;
+                music += &Breakdown::from_rows(
+                    node_range.untransposed_rows(layout),
+                    &ch,
+                    music_types,
+                    start_stroke,
+                );
+            }
         }
-    }
+        music
+    });
     // Determine if this node is (not) a duffer.  A node is a duffer it doesn't include any music
-    // of types considered 'non-duffer'.
+    // of types considered 'non-duffer' (at either stroke).
     //
     // TODO: Determine how close to the ends of this node the music is generated?
-    let non_duffer = music_types
-        .iter()
-        .zip_eq(&music.counts)
-        .any(|(music_type, count)| music_type.non_duffer() && *count > 0);
+    let is_non_duffer = |breakdown: &Breakdown| {
+        music_types
+            .iter()
+            .zip_eq(&breakdown.counts)
+            .any(|(music_type, count)| music_type.non_duffer() && *count > 0)
+    };
+    let non_duffer = is_non_duffer(&music.hand) || is_non_duffer(&music.back);
+    let place_bells = place_bells(node_range, layout, part_heads);
+    // Add the course head weights for every row in every part
+    let mut extra_score = Score::from(0.0);
+    if let Some(source_ch) = node_range.node_id.course_head() {
+        for ph in part_heads {
+            let ch = ph * source_ch;
+            for (mask, weight) in &query.course_head_row_weights {
+                if mask.matches(&ch) {
+                    extra_score = extra_score + *weight * node_range.per_part_length.0 as f32;
+                }
+            }
+        }
+    }
+
+    let (forbidden, required_rows) = row_constraints(node_range, query, part_heads);
+    let course_head_matches = match node_range.node_id.course_head() {
+        Some(source_ch) => query
+            .course_head_counts
+            .iter()
+            .map(|(masks, _)| {
+                part_heads
+                    .iter()
+                    .filter(|ph| {
+                        let ch = *ph * source_ch;
+                        masks.iter().any(|mask| mask.matches(&ch))
+                    })
+                    .count()
+            })
+            .collect_vec(),
+        None => vec![0; query.course_head_counts.len()],
+    };
 
     Node {
         per_part_length: node_range.per_part_length,
@@ -614,6 +793,11 @@ fn build_node(
 
         method_counts: node_range.method_counts.clone(),
         music,
+        extra_score,
+        place_bells,
+        forbidden,
+        required_rows,
+        course_head_matches,
 
         duffer: !non_duffer,
         // Distances will be computed during optimisation passes
@@ -625,12 +809,19 @@ fn build_node(
         // Distances to rounds are computed later, but the distance is an lower bound,
         // so we can set it to 0 without breaking any invariants.
         lb_distance_to_rounds: 0,
+        // Score bounds are also computed by an optimisation pass, and infinity is always a valid
+        // upper bound
+        ub_score_per_row_to_rounds: f32::INFINITY,
+        est_score_per_row_to_rounds: None,
 
         successors: node_range
             .links()
             .iter()
             .cloned()
-            .map(|(idx, id, rotation)| Link::new(id, idx, rotation))
+            .map(|(idx, id, rotation)| {
+                let music = join_music(node_range, idx, &id, layout, music_types, part_heads);
+                Link::new(id, idx, rotation, music)
+            })
             .collect_vec(),
 
         // These are populated in separate passes once all the `Node`s have been created
@@ -638,3 +829,316 @@ fn build_node(
         predecessors: Vec::new(),
     }
 }
+
+/// Determine, for each possible [`Stroke`] of a node's first row, whether that node contains any
+/// forbidden rows and which required rows it contains.
+fn row_constraints(
+    node_range: &NodeRange,
+    query: &Query,
+    part_heads: &[RowBuf],
+) -> (PerStroke<bool>, PerStroke<u64>) {
+    let source_ch = match node_range.node_id.course_head() {
+        Some(ch) => ch,
+        None => return Default::default(),
+    };
+    if query.required_rows.is_empty() && query.forbidden_rows.is_empty() {
+        return Default::default(); // Skip checking every row if there are no row constraints
+    }
+
+    let mut forbidden = PerStroke::new(false, false);
+    let mut required_rows = PerStroke::new(0u64, 0u64);
+    let mut row = RowBuf::rounds(Stage::ONE);
+    for ph in part_heads {
+        let ch = ph * source_ch;
+        for (i, untransposed_row) in node_range.untransposed_rows(&query.layout).enumerate() {
+            ch.mul_into(untransposed_row, &mut row).unwrap();
+            for start_stroke in [Stroke::Hand, Stroke::Back] {
+                let stroke = start_stroke.offset(i);
+                let is_forbidden = query
+                    .forbidden_rows
+                    .iter()
+                    .any(|(regex, strokes)| strokes.contains(stroke) && regex.matches(&row));
+                if is_forbidden {
+                    *forbidden.get_mut(start_stroke) = true;
+                }
+                for (idx, (regex, strokes)) in query.required_rows.iter().enumerate() {
+                    if strokes.contains(stroke) && regex.matches(&row) {
+                        *required_rows.get_mut(start_stroke) |= 1 << idx;
+                    }
+                }
+            }
+        }
+    }
+    (forbidden, required_rows)
+}
+
+/// Compute the music generated across the join between a node and the first row after one of its
+/// outgoing [`Link`](crate::layout::Link)s, summed over all parts.  Joins between parts (i.e.
+/// from the last row of one part to the first row of the next) aren't part of any `Link`, so are
+/// never scored.
+fn join_music(
+    node_range: &NodeRange,
+    link_idx: LinkIdx,
+    succ_id: &NodeId,
+    layout: &Layout,
+    music_types: &[MusicType],
+    part_heads: &[RowBuf],
+) -> PerStroke<Breakdown> {
+    let zero = || PerStroke::from_fn(|_| Breakdown::zero(music_types.len()));
+    let source_ch = match node_range.node_id.course_head() {
+        Some(ch) => ch,
+        None => return zero(),
+    };
+    // Joins into rounds don't generate music, since the final rounds isn't part of the
+    // composition
+    if !succ_id.is_standard() || !music_types.iter().any(MusicType::is_multi_row) {
+        return zero();
+    }
+
+    let link = &layout.links[link_idx];
+    // `link.from` is the last row of the node (e.g. the lead end)
+    let last_row = source_ch
+        * layout.blocks[link.from.block]
+            .get_row(link.from.row)
+            .unwrap();
+    let next_row = &(source_ch * link.ch_transposition.as_row())
+        * layout.blocks[link.to.block].get_row(link.to.row).unwrap();
+
+    PerStroke::from_fn(|next_stroke: Stroke| {
+        let mut music = Breakdown::zero(music_types.len());
+        for ph in part_heads {
+            music += &Breakdown::from_join(
+                &(ph * last_row.as_row()),
+                &(ph * next_row.as_row()),
+                music_types,
+                !next_stroke,
+            );
+        }
+        music
+    })
+}
+
+/// Every [`PlaceBell`] which must be rung for a composition to be all-the-work.  For each method,
+/// every working bell has to ring every working place bell and every hunt bell has to ring its own
+/// place (where the hunt places are those fixed by the method's plain lead head).
+fn all_place_bells(layout: &Layout) -> Vec<PlaceBell> {
+    let mut place_bells = Vec::new();
+    for (block_idx, block) in layout.blocks.iter_enumerated() {
+        let lead_len = layout.lead_lens[block_idx];
+        let lead_head = block
+            .get_row(lead_len)
+            .unwrap_or_else(|| block.leftover_row());
+        let is_working = lead_head
+            .bell_iter()
+            .enumerate()
+            .map(|(place, bell)| bell.index() != place)
+            .collect_vec();
+        for place in 0..layout.stage.num_bells() {
+            for bell in layout.stage.bells() {
+                let is_place_bell = match is_working[place] {
+                    true => is_working[bell.index()],
+                    false => bell.index() == place,
+                };
+                if is_place_bell {
+                    place_bells.push(PlaceBell {
+                        block: block_idx,
+                        bell,
+                        place,
+                    });
+                }
+            }
+        }
+    }
+    place_bells
+}
+
+/// Determine which [`PlaceBell`]s are rung by a node, in any part.  A lead's place bells are only
+/// counted if the node contains that whole lead, so partial leads (e.g. snap starts or finishes)
+/// never count towards all-the-work.
+fn place_bells(node_range: &NodeRange, layout: &Layout, part_heads: &[RowBuf]) -> Vec<PlaceBell> {
+    let std_id = match &node_range.node_id {
+        NodeId::Standard(std_id) => std_id,
+        NodeId::ZeroLengthEnd => return Vec::new(),
+    };
+    let block_idx = std_id.row_idx.block;
+    let block = &layout.blocks[block_idx];
+    let lead_len = layout.lead_lens[block_idx];
+
+    let mut place_bells = Vec::new();
+    let mut lead_head = RowBuf::rounds(Stage::ONE);
+    for i in 0..node_range.per_part_length.0 {
+        let row_idx = (std_id.row_idx.row + i) % block.len();
+        if row_idx % lead_len != 0 {
+            continue; // Only lead heads determine place bells
+        }
+        if i + lead_len > node_range.per_part_length.0 {
+            break; // The last lead isn't rung in full by this node
+        }
+        let untransposed_lead_head = block.get_row(row_idx).unwrap();
+        for ph in part_heads {
+            let ch = ph * std_id.course_head.as_ref();
+            ch.mul_into(untransposed_lead_head, &mut lead_head).unwrap();
+            for (place, bell) in lead_head.bell_iter().enumerate() {
+                place_bells.push(PlaceBell {
+                    block: block_idx,
+                    bell,
+                    place,
+                });
+            }
+        }
+    }
+    place_bells
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use bellframe::{music::Regex, Bell, Mask, RowBuf, Stage};
+
+    use crate::{
+        layout::new::SpliceStyle,
+        music::{MusicType, Patterns, Score, StrokeSet},
+        test_utils::{config, custom_layout, layout, plain_bob, query, run},
+        OptRange, QueryError,
+    };
+
+    #[test]
+    fn required_and_forbidden_rows() {
+        // `135264` is the first lead head of the plain course, and is counted as music so that we
+        // can check which compositions contain it
+        let row = Regex::parse("135264");
+        let row_counts = |required: bool| {
+            let mut query = query(
+                layout(&[plain_bob(Stage::MINOR)], "123456", "123456"),
+                0..200,
+            );
+            query.music_types = vec![MusicType::new(
+                Patterns::Rows(vec![row.clone()]),
+                1.0,
+                OptRange::default(),
+                true,
+                StrokeSet::Both,
+            )];
+            let rows = vec![(row.clone(), StrokeSet::Both)];
+            if required {
+                query.required_rows = rows;
+            } else {
+                query.forbidden_rows = rows;
+            }
+            let comps = run(query, config());
+            assert!(!comps.is_empty());
+            comps.iter().map(|c| c.music.counts[0]).collect::<Vec<_>>()
+        };
+        assert!(row_counts(true).iter().all(|&n| n > 0));
+        assert!(row_counts(false).iter().all(|&n| n == 0));
+    }
+
+    #[test]
+    fn course_head_row_weights() {
+        let mut query = query(
+            layout(&[plain_bob(Stage::MINOR)], "123456", "123456"),
+            0..500,
+        );
+        query.course_head_row_weights = vec![(Mask::parse("123456"), 0.5)];
+        let graph = query.unoptimised_graph();
+        let rounds = RowBuf::rounds(Stage::MINOR);
+        for (id, node) in graph.nodes() {
+            let is_plain_course = id.course_head().map_or(false, |ch| *ch == *rounds);
+            let expected_weight = if is_plain_course { 0.5 } else { 0.0 };
+            // Every row of the plain course is weighted, regardless of how it's entered
+            let expected_score = expected_weight * node.per_part_length.0 as f32;
+            assert_eq!(node.extra_score, Score::from(expected_score));
+        }
+    }
+
+    #[test]
+    fn atw_needs_every_place_bell() {
+        // The tenor can only ring its home place bell in tenors-together compositions, so these
+        // can never be all-the-work
+        let mut query = query(
+            layout(&[plain_bob(Stage::MINOR)], "123456", "123456"),
+            0..500,
+        );
+        query.atw = true;
+        let graph = query.unoptimised_graph();
+        // 5 working bells ringing 5 working place bells, plus the treble ringing the lead
+        assert_eq!(graph.place_bells().len(), 5 * 5 + 1);
+        assert_eq!(
+            graph.unrung_place_bell().unwrap().bell,
+            Bell::tenor(Stage::MINOR)
+        );
+        assert!(matches!(
+            crate::run_query(Arc::new(query), &mut config(), None),
+            Err(QueryError::UnrungPlaceBell(_))
+        ));
+    }
+
+    #[test]
+    fn atw_plain_course() {
+        // With every course available, every place bell can be rung.  The plain course is the
+        // shortest all-the-work composition, since every bell rings every place bell once
+        let atw_query = |len_range| {
+            let layout = custom_layout(
+                &[plain_bob(Stage::MINOR)],
+                SpliceStyle::LeadLabels,
+                Mask::fix_bells(Stage::MINOR, vec![]),
+                "123456",
+                "123456",
+            );
+            let mut query = query(layout, len_range);
+            query.atw = true;
+            query
+        };
+        assert!(atw_query(0..60)
+            .unoptimised_graph()
+            .unrung_place_bell()
+            .is_none());
+        let comps = run(atw_query(0..61), config());
+        assert!(!comps.is_empty());
+        assert!(comps.iter().all(|c| c.length == 60));
+        assert!(run(atw_query(0..60), config()).is_empty());
+    }
+
+    #[test]
+    fn wrap_across_lead_end() {
+        // In the plain course of Plain Bob Major, the 4th lead end is `18765432` and the next
+        // lead head is `18674523`, wrapping `432|1`.  The row before that lead end (`81674523`)
+        // doesn't wrap into the lead head, and no other plain lead end of the course wraps.
+        let mut query = query(
+            layout(&[plain_bob(Stage::MAJOR)], "12345678", "12345678"),
+            0..5000,
+        );
+        query.music_types = vec![MusicType::new(
+            Patterns::RowPairs(Patterns::wrapped_runs(Stage::MAJOR, 4)),
+            1.0,
+            OptRange::default(),
+            true,
+            StrokeSet::Both,
+        )];
+        let graph = query.unoptimised_graph();
+
+        let rounds = RowBuf::rounds(Stage::MAJOR);
+        // The 7th lead comes round, so has no links
+        for lead_idx in 0..6 {
+            // Find the plain link out of this lead of the plain course
+            let (_id, node) = graph
+                .nodes()
+                .find(|(id, _node)| {
+                    id.std_id().map_or(false, |std_id| {
+                        *std_id.course_head == *rounds && std_id.row_idx.row == lead_idx * 16
+                    })
+                })
+                .unwrap();
+            let plain_link = node
+                .successors()
+                .iter()
+                .find(|link| !query.layout.links[link.source_idx].is_call())
+                .unwrap();
+
+            let expected_count = if lead_idx == 3 { 1 } else { 0 };
+            assert_eq!(plain_link.music.hand.counts, vec![expected_count]);
+            assert_eq!(plain_link.music.back.counts, vec![expected_count]);
+        }
+    }
+}
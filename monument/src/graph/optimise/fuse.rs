@@ -0,0 +1,227 @@
+use std::collections::HashMap;
+
+use itertools::Itertools;
+
+use crate::{
+    graph::{Graph, Link, Node},
+    layout::{
+        node_range::{PerPartLength, TotalLength},
+        NodeId, Rotation, StandardNodeId,
+    },
+    music::PerStroke,
+    Query,
+};
+
+/// Fuses chains of nodes which must always be rung together (i.e. where a node's only successor
+/// has that node as its only predecessor) into single nodes.  This doesn't change the set of
+/// compositions generated by the graph, but does shrink the graph that the search traverses.
+pub(super) fn fuse_chains(graph: &mut Graph, query: &Query) {
+    // Maps the ID of every node which has been removed onto the ID of the node it was fused into
+    let mut fused_ids = HashMap::<NodeId, NodeId>::new();
+
+    let ids = graph.ids().cloned().collect_vec();
+    for id in ids {
+        // Keep absorbing successors until the chain starting at `id` can't be extended
+        while let Some(succ_id) = fusable_successor(graph, &id, &fused_ids, query) {
+            let succ = graph.nodes.remove(&succ_id).unwrap();
+            let num_parts = graph.num_parts;
+            let node = graph.nodes.get_mut(&id).unwrap();
+            let link = node.successors.pop().unwrap();
+            fuse(node, link, succ, query, num_parts);
+            // The successors of `succ` are now entered from `id`
+            for succ_link in node.successors.clone() {
+                if let Some(next_node) = graph.nodes.get_mut(&succ_link.id) {
+                    for pred_link in &mut next_node.predecessors {
+                        if pred_link.id == succ_id {
+                            pred_link.id = id.clone();
+                        }
+                    }
+                }
+            }
+            fused_ids.insert(succ_id, id.clone());
+        }
+    }
+
+    if fused_ids.is_empty() {
+        return; // Nothing was fused, so there are no references to update
+    }
+    // Redirect falseness and end references to the nodes which have been fused away
+    let std_fused_ids = fused_ids
+        .keys()
+        .filter_map(|id| {
+            Some((
+                id.std_id()?.clone(),
+                resolve(id, &fused_ids).std_id()?.clone(),
+            ))
+        })
+        .collect::<HashMap<StandardNodeId, StandardNodeId>>();
+    for node in graph.nodes.values_mut() {
+        node.false_nodes = std::mem::take(&mut node.false_nodes)
+            .into_iter()
+            .map(|id| std_fused_ids.get(&id).cloned().unwrap_or(id))
+            .unique()
+            .collect_vec();
+    }
+    for (id, _end) in &mut graph.end_nodes {
+        *id = resolve(id, &fused_ids).clone();
+    }
+}
+
+/// If the node with a given [`NodeId`] can be fused with its successor, then return the
+/// [`NodeId`] of that successor.
+fn fusable_successor(
+    graph: &Graph,
+    id: &NodeId,
+    fused_ids: &HashMap<NodeId, NodeId>,
+    query: &Query,
+) -> Option<NodeId> {
+    let node = graph.get_node(id)?;
+    let link = node.successors().iter().exactly_one().ok()?;
+    let succ = graph.get_node(&link.id)?;
+    let pred_link = succ.predecessors().iter().exactly_one().ok()?;
+
+    let is_only_route = pred_link.id == *id && pred_link.source_idx == link.source_idx;
+    // Start nodes can be reached without going through their predecessor
+    let is_only_entry = is_only_route && !succ.is_start && link.id != *id;
+    // The search counts changes of method, courses and part rotations as links are taken, and
+    // duffers as nodes are added, so these can't be hidden inside a node
+    let is_counted = link.is_splice(&query.layout)
+        || query.layout.links[link.source_idx].is_call()
+        || link.rotation != 0
+        || node.duffer != succ.duffer;
+    // Fusing a node with a node it's false against would create a node which is false against
+    // itself, which the search wouldn't detect
+    let is_false = node
+        .false_nodes()
+        .iter()
+        .any(|false_id| resolve(&NodeId::Standard(false_id.clone()), fused_ids) == &link.id);
+
+    (id.is_standard() && is_only_entry && !is_counted && !is_false).then(|| link.id.clone())
+}
+
+/// Combine `node` with its successor `succ`, which is reached through `link`.  The resulting node
+/// generates exactly the same rows, music and score as ringing `node` then `succ`.
+fn fuse(node: &mut Node, link: Link, succ: Node, query: &Query, num_parts: Rotation) {
+    // The first row of `succ` comes `per_part_length` rows after the first row of `node`
+    let len = node.per_part_length.0;
+
+    node.label
+        .push_str(&query.layout.links[link.source_idx].display_name);
+    node.label.push_str(&succ.label);
+    node.end = succ.end;
+    node.successors = succ.successors;
+    node.false_nodes.extend(succ.false_nodes);
+
+    node.per_part_length = PerPartLength(len + succ.per_part_length.0);
+    node.total_length = TotalLength(node.total_length.0 + succ.total_length.0);
+    node.method_counts += &succ.method_counts;
+    node.music = PerStroke::from_fn(|stroke| {
+        let succ_stroke = stroke.offset(len);
+        &(node.music.get(stroke) + link.music.get(succ_stroke)) + succ.music.get(succ_stroke)
+    });
+    node.extra_score = node.extra_score + succ.extra_score + link.weight(query, num_parts);
+    node.place_bells.extend(succ.place_bells);
+    node.forbidden = PerStroke::from_fn(|stroke| {
+        *node.forbidden.get(stroke) || *succ.forbidden.get(stroke.offset(len))
+    });
+    node.required_rows = PerStroke::from_fn(|stroke| {
+        node.required_rows.get(stroke) | succ.required_rows.get(stroke.offset(len))
+    });
+    // `link` isn't a call, so `succ` is in the same course as `node` and its course head matches
+    // are never counted by the search
+
+    node.lb_distance_to_non_duffer = succ.lb_distance_to_non_duffer;
+    node.required |= succ.required;
+    node.lb_distance_to_rounds = succ.lb_distance_to_rounds;
+    node.ub_score_per_row_to_rounds = succ.ub_score_per_row_to_rounds;
+    node.est_score_per_row_to_rounds = succ.est_score_per_row_to_rounds;
+}
+
+/// Follow the chain of fused nodes from `id` to the node which is still in the [`Graph`]
+fn resolve<'a>(mut id: &'a NodeId, fused_ids: &'a HashMap<NodeId, NodeId>) -> &'a NodeId {
+    while let Some(new_id) = fused_ids.get(id) {
+        id = new_id;
+    }
+    id
+}
+
+#[cfg(test)]
+mod tests {
+    use bellframe::{Bell, Mask, RowBuf, Stage};
+    use itertools::Itertools;
+
+    use crate::{
+        graph::optimise::{passes, Pass},
+        layout::new::SpliceStyle,
+        music::Score,
+        test_utils::{config, custom_layout, layout, method, plain_bob, query, run, runs},
+        Config, Query, Strategy,
+    };
+
+    /// [`passes::default`], without the passes which fuse nodes together
+    fn passes_without_fusion() -> Vec<Pass> {
+        vec![
+            passes::strip_refs(),
+            passes::compute_distances(),
+            passes::strip_long_nodes(),
+            passes::compute_duffer_distances(),
+            passes::strip_duff_nodes(),
+            passes::single_start_or_end_required(),
+            passes::required_prefix(),
+            passes::required_rows(),
+            passes::required_courses(),
+            passes::remove_nodes_false_against_required(),
+            passes::required_music(),
+            passes::compute_score_bounds(),
+            passes::compute_score_estimates(),
+        ]
+    }
+
+    /// Every composition generated by `query`, as sorted pairs of display strings and scores
+    fn all_comps(query: &Query, optimisation_passes: Vec<Pass>) -> Vec<(String, Score)> {
+        let comps = run(
+            query.clone(),
+            Config {
+                strategy: Strategy::Enumerate,
+                optimisation_passes,
+                ..config()
+            },
+        );
+        comps
+            .iter()
+            .map(|c| (c.display_string(&query.layout), c.avg_score))
+            .sorted()
+            .collect_vec()
+    }
+
+    fn check_fusion_preserves_comps(query: Query) {
+        let fused_comps = all_comps(&query, passes::default());
+        assert!(!fused_comps.is_empty());
+        assert_eq!(fused_comps, all_comps(&query, passes_without_fusion()));
+    }
+
+    #[test]
+    fn splice_at_calls() {
+        // Splices which run false are removed, and the remaining leads are fused together
+        let methods = [
+            plain_bob(Stage::MINOR),
+            method("Test Bob", Stage::MINOR, "x14x16x16,12"),
+        ];
+        let tenor_mask = Mask::fix_bells(Stage::MINOR, vec![Bell::tenor(Stage::MINOR)]);
+        let layout = custom_layout(&methods, SpliceStyle::Calls, tenor_mask, "123456", "123456");
+        let mut query = query(layout, 0..73);
+        query.music_types = vec![runs(Stage::MINOR, 4)];
+        check_fusion_preserves_comps(query);
+    }
+
+    #[test]
+    fn multi_part() {
+        let mut query = query(
+            layout(&[plain_bob(Stage::MINOR)], "123456", "123456"),
+            0..200,
+        );
+        query.part_head = RowBuf::parse("132456").unwrap();
+        query.music_types = vec![runs(Stage::MINOR, 4)];
+        check_fusion_preserves_comps(query);
+    }
+}
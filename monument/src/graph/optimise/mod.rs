@@ -213,7 +213,9 @@ impl<'graph> NodeViewMut<'graph> {
 // BUILTIN PASSES //
 ////////////////////
 
+mod fuse; // Fusing chains of nodes which are always rung together
 mod music; // Proving nodes as required/unusable based on music requirements
+mod score_bounds; // Bounds and estimates of the score which can be generated before rounds
 mod strip_refs; // Strip references to non-existent nodes
 
 pub mod passes {
@@ -229,6 +231,8 @@ pub mod passes {
     pub fn default() -> Vec<Pass> {
         vec![
             strip_refs(),
+            strip_false_links(),
+            fuse_chains(),
             // Distance-related optimisation
             compute_distances(),
             strip_long_nodes(),
@@ -237,8 +241,14 @@ pub mod passes {
             strip_duff_nodes(),
             // Required node optimisation
             single_start_or_end_required(),
+            required_prefix(),
+            required_rows(),
+            required_courses(),
             remove_nodes_false_against_required(),
             required_music(),
+            // Score bounds and estimates (used to prune and guide the search)
+            compute_score_bounds(),
+            compute_score_estimates(),
         ]
     }
 
@@ -250,12 +260,52 @@ pub mod passes {
         Pass::Single(Box::new(super::strip_refs::strip_refs))
     }
 
+    /// A [`Pass`] which removes links between nodes which are false against each other (e.g.
+    /// splices into a method which immediately runs false).  Any composition which takes such a
+    /// link is false, but removing them lets [`fuse_chains`] combine the remaining nodes.
+    pub fn strip_false_links() -> Pass {
+        Pass::Single(Box::new(|graph: &mut Graph, _| {
+            for (_id, node) in graph.nodes_mut() {
+                let false_ids = node
+                    .false_nodes()
+                    .iter()
+                    .map(|id| NodeId::Standard(id.clone()))
+                    .collect::<HashSet<_>>();
+                // Falseness is symmetric, so the links can be removed from both ends
+                node.successors_mut()
+                    .retain(|link| !false_ids.contains(&link.id));
+                node.predecessors_mut()
+                    .retain(|link| !false_ids.contains(&link.id));
+            }
+        }))
+    }
+
+    /// A [`Pass`] which fuses every node whose only successor has it as its only predecessor into
+    /// that successor, so that the search has fewer nodes to traverse.
+    pub fn fuse_chains() -> Pass {
+        Pass::Single(Box::new(super::fuse::fuse_chains))
+    }
+
     /// Creates a [`Pass`] which recomputes the distances to and from rounds for every node,
     /// removing any which can't reach rounds in either direction.
     pub fn required_music() -> Pass {
         Pass::Single(Box::new(super::music::required_music_min))
     }
 
+    /// A [`Pass`] which computes an upper bound on the score per row that can be generated after
+    /// every node.  This never changes the shape of the graph, but lets exact searches prune
+    /// prefixes which can't beat the compositions found so far.
+    pub fn compute_score_bounds() -> Pass {
+        Pass::Single(Box::new(super::score_bounds::compute_score_bounds))
+    }
+
+    /// A [`Pass`] which estimates the score per row that can be generated between every node and
+    /// rounds.  This never changes the shape of the graph, but can be used to guide the search
+    /// (see [`Priority::Estimated`](crate::Priority::Estimated)).
+    pub fn compute_score_estimates() -> Pass {
+        Pass::Single(Box::new(super::score_bounds::compute_score_estimates))
+    }
+
     /* Distance related passes */
 
     /// Creates a [`Pass`] which recomputes the distances to and from rounds for every node,
@@ -345,6 +395,82 @@ pub mod passes {
         }))
     }
 
+    /// A [`Pass`] which removes any start nodes which can't begin the [`Query::comp_prefix`], and
+    /// marks every node shared by all the ways of ringing that prefix as required.
+    pub fn required_prefix() -> Pass {
+        Pass::Single(Box::new(|graph: &mut Graph, query: &Query| {
+            let prefix = match &query.comp_prefix {
+                Some(prefix) => prefix,
+                None => return, // No prefix to match
+            };
+            let fixed_prefixes = graph.parse_prefix(prefix, &query.layout);
+
+            let used_starts = fixed_prefixes
+                .iter()
+                .map(|p| (p.start_id.clone(), p.start_idx, p.rotation))
+                .collect::<HashSet<_>>();
+            graph.retain_start_nodes(|start| used_starts.contains(start));
+
+            // Nodes which appear in every way of ringing the prefix must be in every composition
+            let mut required_ids = match fixed_prefixes.first() {
+                Some(p) => p.node_ids().cloned().collect::<HashSet<_>>(),
+                None => return,
+            };
+            for p in &fixed_prefixes[1..] {
+                let ids = p.node_ids().collect::<HashSet<_>>();
+                required_ids.retain(|id| ids.contains(id));
+            }
+            for id in required_ids {
+                if let Some(node) = graph.get_node_mut(&id) {
+                    node.required = true;
+                }
+            }
+        }))
+    }
+
+    /// A [`Pass`] which marks a node as required if it's the only node which contains one of the
+    /// [`Query::required_rows`] (because all compositions must contain that row).
+    pub fn required_rows() -> Pass {
+        Pass::Single(Box::new(|graph: &mut Graph, query: &Query| {
+            for row_idx in 0..query.required_rows.len() {
+                let mask = 1u64 << row_idx;
+                let single_node_id = graph
+                    .nodes()
+                    .filter(|(_id, node)| {
+                        let rows = node.required_rows();
+                        (rows.hand | rows.back) & mask != 0
+                    })
+                    .map(|(id, _node)| id)
+                    .exactly_one();
+                if let Ok(id) = single_node_id {
+                    let id = id.clone();
+                    graph.get_node_mut(&id).unwrap().required = true;
+                }
+            }
+        }))
+    }
+
+    /// A [`Pass`] which marks a node as required if it's the only node in a course which is
+    /// required by [`Query::course_head_counts`].
+    pub fn required_courses() -> Pass {
+        Pass::Single(Box::new(|graph: &mut Graph, query: &Query| {
+            for (idx, (_masks, range)) in query.course_head_counts.iter().enumerate() {
+                if range.min.map_or(true, |min| min == 0) {
+                    continue; // This course isn't required
+                }
+                let single_node_id = graph
+                    .nodes()
+                    .filter(|(_id, node)| node.course_head_matches()[idx] > 0)
+                    .map(|(id, _node)| id)
+                    .exactly_one();
+                if let Ok(id) = single_node_id {
+                    let id = id.clone();
+                    graph.get_node_mut(&id).unwrap().required = true;
+                }
+            }
+        }))
+    }
+
     /// A [`Pass`] which removes any nodes which are false against a node marked as required
     pub fn remove_nodes_false_against_required() -> Pass {
         Pass::Single(Box::new(|graph: &mut Graph, _| {
@@ -17,11 +17,13 @@ const ITERATION_LIMIT: usize = 10_000;
 pub(super) fn required_music_min(graph: &mut Graph, query: &Query) {
     // log::debug!("\n\n\n");
 
-    // For each `MusicType`, maps its index to minimum count
+    // For each `MusicType`, maps its index to minimum count.  Multi-row music can also be
+    // generated by the links between nodes, so we can't reason about it by only looking at nodes
     let min_music_counts = query
         .music_types
         .iter()
         .enumerate()
+        .filter(|(_i, ty)| !ty.is_multi_row())
         .filter_map(|(i, ty)| ty.count_range().min.map(|min| (i, min)))
         .collect::<HashMap<_, _>>();
 
@@ -36,7 +38,7 @@ pub(super) fn required_music_min(graph: &mut Graph, query: &Query) {
         .filter(|(_id, node)| {
             min_music_counts
                 .iter()
-                .any(|(ty_idx, _)| node.music().counts[*ty_idx] > 0)
+                .any(|(ty_idx, _)| node.max_music().counts[*ty_idx] > 0)
         })
         .map(|(id, node)| (id.standard().unwrap(), node))
         .partition::<Vec<_>, _>(|(_id, node)| node.required);
@@ -52,8 +54,9 @@ pub(super) fn required_music_min(graph: &mut Graph, query: &Query) {
     };
     for (_id, node) in required_nodes {
         // Non-required nodes aren't required to get music which the required nodes can already
-        // achieve
-        counts_needed_from_non_required_nodes.saturating_sub_assign(node.music());
+        // achieve.  We don't know which stroke each node will start at, so we take an upper bound
+        // on the music to avoid marking nodes as required when they aren't
+        counts_needed_from_non_required_nodes.saturating_sub_assign(&node.max_music());
     }
 
     // Do tree search over the non-required interesting nodes, determining which combinations of
@@ -162,7 +165,7 @@ fn search_nodes<'a, 'gr: 'a>(
 
     // Add the node
     nodes_used.insert(id);
-    let counts_needed_with_this_node = counts_needed.saturating_sub(node.music());
+    let counts_needed_with_this_node = counts_needed.saturating_sub(&node.max_music());
     // Continue searching, assuming that this node is used
     search_nodes(
         nodes.clone(),
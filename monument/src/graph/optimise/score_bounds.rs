@@ -0,0 +1,129 @@
+use std::collections::HashMap;
+
+use itertools::Itertools;
+
+use crate::{graph::Graph, layout::NodeId, music::Stroke, Query};
+
+/// Compute [`Node::ub_score_per_row_to_rounds`](crate::graph::Node::ub_score_per_row_to_rounds)
+/// for every node in the [`Graph`]
+pub(super) fn compute_score_bounds(graph: &mut Graph, query: &Query) {
+    let num_parts = graph.num_parts();
+
+    // Start each node's bound as the best score per row of its immediate successors
+    let mut bounds = HashMap::<NodeId, f32>::new();
+    for (id, node) in graph.nodes() {
+        let mut bound = f32::NEG_INFINITY;
+        for link in node.successors() {
+            let succ_node = match graph.get_node(&link.id) {
+                Some(n) => n,
+                None => continue, // Links to non-existent nodes can't be taken
+            };
+            if succ_node.length() == 0 {
+                continue; // 0-length nodes have no rows to generate score
+            }
+            let link_score = link.score(query, num_parts);
+            let node_score = succ_node.score();
+            for stroke in [Stroke::Hand, Stroke::Back] {
+                let score = *link_score.get(stroke) + *node_score.get(stroke);
+                bound = bound.max(score.0 / succ_node.length() as f32);
+            }
+        }
+        bounds.insert(id.clone(), bound);
+    }
+
+    // Propagate the bounds backwards until they stop changing.  Anything which can be rung after a
+    // node's successor can also be rung after that node.
+    let mut to_update = bounds.keys().cloned().collect::<Vec<_>>();
+    while let Some(id) = to_update.pop() {
+        let bound = bounds[&id];
+        for pred_link in graph.get_node(&id).unwrap().predecessors() {
+            if let Some(pred_bound) = bounds.get_mut(&pred_link.id) {
+                if bound > *pred_bound {
+                    *pred_bound = bound;
+                    to_update.push(pred_link.id.clone());
+                }
+            }
+        }
+    }
+
+    for (id, node) in graph.nodes_mut() {
+        let bound = bounds[id];
+        // Nodes which can't reach any rows before rounds can't generate any more score
+        node.ub_score_per_row_to_rounds = if bound == f32::NEG_INFINITY {
+            0.0
+        } else {
+            bound
+        };
+    }
+}
+
+/// The maximum number of iterations used to compute score estimates
+const MAX_ESTIMATE_ITERS: usize = 100;
+/// Once no estimate changes by more than this in one iteration, the estimates are considered to
+/// have converged
+const ESTIMATE_TOLERANCE: f32 = 1e-3;
+
+/// Compute [`Node::est_score_per_row_to_rounds`](crate::graph::Node::est_score_per_row_to_rounds)
+/// for every node in the [`Graph`].  A node's estimate is the best score per row of taking one
+/// of its successors and then ringing the successor's estimate for the shortest distance to
+/// rounds.
+pub(super) fn compute_score_estimates(graph: &mut Graph, query: &Query) {
+    let num_parts = graph.num_parts();
+
+    // For each node, the ID, score, length and distance to rounds of every successor which
+    // contains rows
+    let mut succ_data = HashMap::<NodeId, Vec<(NodeId, f32, f32, f32)>>::new();
+    for (id, node) in graph.nodes() {
+        let succs = node
+            .successors()
+            .iter()
+            .filter_map(|link| {
+                let succ_node = graph.get_node(&link.id)?;
+                if succ_node.length() == 0 {
+                    return None; // 0-length nodes have no rows to generate score
+                }
+                let link_score = link.score(query, num_parts);
+                let node_score = succ_node.score();
+                let score =
+                    (link_score.hand + node_score.hand).max(link_score.back + node_score.back);
+                Some((
+                    link.id.clone(),
+                    score.0,
+                    succ_node.length() as f32,
+                    succ_node.lb_distance_to_rounds as f32,
+                ))
+            })
+            .collect_vec();
+        succ_data.insert(id.clone(), succs);
+    }
+
+    // Repeatedly update the estimates until they converge
+    let mut estimates = graph
+        .ids()
+        .map(|id| (id.clone(), 0f32))
+        .collect::<HashMap<_, _>>();
+    for _ in 0..MAX_ESTIMATE_ITERS {
+        let mut max_change = 0f32;
+        for (id, succs) in &succ_data {
+            let new_estimate = succs
+                .iter()
+                .map(|(succ_id, score, length, dist)| {
+                    (score + estimates[succ_id] * dist) / (length + dist)
+                })
+                .fold(f32::NEG_INFINITY, f32::max);
+            if new_estimate == f32::NEG_INFINITY {
+                continue; // Nodes which can't reach any more rows keep an estimate of 0
+            }
+            let estimate = estimates.get_mut(id).unwrap();
+            max_change = max_change.max((new_estimate - *estimate).abs());
+            *estimate = new_estimate;
+        }
+        if max_change < ESTIMATE_TOLERANCE {
+            break;
+        }
+    }
+
+    for (id, node) in graph.nodes_mut() {
+        node.est_score_per_row_to_rounds = Some(estimates[id]);
+    }
+}
@@ -0,0 +1,204 @@
+//! Code for matching a fixed composition prefix (given as a call string) against the nodes of a
+//! [`Graph`].
+
+use std::collections::{HashSet, VecDeque};
+
+use itertools::Itertools;
+
+use crate::layout::{Layout, LinkIdx, NodeId, Rotation, StartIdx};
+
+use super::Graph;
+
+/// A fixed route through a [`Graph`], starting at one of its start nodes.  Every composition
+/// generated from a [`Query`](crate::Query) with a `comp_prefix` must begin with one of these.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct FixedPrefix {
+    pub start_id: NodeId,
+    pub start_idx: StartIdx,
+    /// The rotation of the start node
+    pub rotation: Rotation,
+    /// The links taken after the start node, along with the node reached by each one
+    pub links: Vec<(LinkIdx, NodeId)>,
+}
+
+impl FixedPrefix {
+    /// The IDs of every node in this prefix, in the order they're rung
+    pub fn node_ids(&self) -> impl Iterator<Item = &NodeId> {
+        std::iter::once(&self.start_id).chain(self.links.iter().map(|(_, id)| id))
+    }
+}
+
+impl Graph {
+    /// Find every [`FixedPrefix`] in `self` whose call string (in the same format as
+    /// [`Comp::display_string`](crate::Comp::display_string), but without the end label) is
+    /// exactly `prefix`.  Where links or nodes have empty labels, the shortest matching route is
+    /// used.  The last node's label may run past the end of `prefix`, since a fused node's label
+    /// covers every node in its chain.
+    pub fn parse_prefix(&self, prefix: &str, layout: &Layout) -> Vec<FixedPrefix> {
+        self.match_call_string(prefix, layout, true)
+    }
+
+    /// Find every route through `self` whose labels make up `string`.  If `allow_overrun` is set,
+    /// then the last node's label can run past the end of `string`.
+    fn match_call_string(
+        &self,
+        string: &str,
+        layout: &Layout,
+        allow_overrun: bool,
+    ) -> Vec<FixedPrefix> {
+        // Breadth-first search over (node, string position) pairs, so that each prefix is matched
+        // with as few nodes as possible
+        let mut frontier = VecDeque::<(FixedPrefix, usize, Rotation)>::new();
+        let mut visited = HashSet::<(NodeId, usize, Rotation)>::new();
+        for (start_id, start_idx, rotation) in &self.start_nodes {
+            let node = match self.get_node(start_id) {
+                Some(n) => n,
+                None => continue,
+            };
+            let label = format!("{}{}", layout.starts[*start_idx].label, node.label());
+            if let Some(new_pos) = match_label(string, 0, &label, allow_overrun) {
+                let fixed_prefix = FixedPrefix {
+                    start_id: start_id.clone(),
+                    start_idx: *start_idx,
+                    rotation: *rotation,
+                    links: Vec::new(),
+                };
+                visited.insert((start_id.clone(), new_pos, *rotation));
+                frontier.push_back((fixed_prefix, new_pos, *rotation));
+            }
+        }
+
+        let mut matches = Vec::new();
+        while let Some((fixed_prefix, pos, rotation)) = frontier.pop_front() {
+            if pos == string.len() {
+                matches.push(fixed_prefix);
+                continue; // Don't extend prefixes which already match the whole string
+            }
+
+            let last_id = fixed_prefix.node_ids().last().unwrap();
+            let node = self.get_node(last_id).unwrap();
+            for link in node.successors() {
+                let succ_node = match self.get_node(&link.id) {
+                    Some(n) => n,
+                    None => continue,
+                };
+                let label = format!(
+                    "{}{}",
+                    layout.links[link.source_idx].display_name,
+                    succ_node.label()
+                );
+                let new_pos = match match_label(string, pos, &label, allow_overrun) {
+                    Some(p) => p,
+                    None => continue,
+                };
+                let new_rotation = (rotation + link.rotation) % self.num_parts;
+                if !visited.insert((link.id.clone(), new_pos, new_rotation)) {
+                    continue;
+                }
+                let mut new_prefix = fixed_prefix.clone();
+                new_prefix.links.push((link.source_idx, link.id.clone()));
+                frontier.push_back((new_prefix, new_pos, new_rotation));
+            }
+        }
+        matches
+    }
+
+    /// Find a complete composition in `self` whose call string (as generated by
+    /// [`Comp::display_string`](crate::Comp::display_string)) is `comp`, returning `None` if no
+    /// such composition exists.
+    pub fn parse_comp(&self, comp: &str, layout: &Layout) -> Option<FixedPrefix> {
+        let end_labels = self
+            .end_nodes
+            .iter()
+            .map(|(_id, end)| end.label(layout))
+            .unique();
+        for end_label in end_labels {
+            let prefix = match comp.strip_suffix(end_label) {
+                Some(p) => p,
+                None => continue,
+            };
+            // The whole composition is given, so labels can't run past the end label
+            let full_comp = self
+                .match_call_string(prefix, layout, false)
+                .into_iter()
+                .find(|p| {
+                    let last_id = p.node_ids().last().unwrap();
+                    let end = self.get_node(last_id).and_then(|node| node.end());
+                    end.map_or(false, |end| end.label(layout) == end_label)
+                });
+            if full_comp.is_some() {
+                return full_comp;
+            }
+        }
+        None
+    }
+}
+
+/// If `label` can come at position `pos` in `string`, returns the position in `string` just after
+/// `label`.  If `allow_overrun` is set and `label` runs past the end of `string`, then the whole
+/// string is matched.
+fn match_label(string: &str, pos: usize, label: &str, allow_overrun: bool) -> Option<usize> {
+    let rest = &string[pos..];
+    if rest.starts_with(label) {
+        Some(pos + label.len())
+    } else if allow_overrun && label.starts_with(rest) {
+        Some(string.len())
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use bellframe::Stage;
+
+    use crate::{
+        test_utils::{config, layout, plain_bob, query, run},
+        QueryError,
+    };
+
+    #[test]
+    fn comp_prefix() {
+        let query = query(
+            layout(&[plain_bob(Stage::MINOR)], "123456", "123456"),
+            0..200,
+        );
+        let layout = &query.layout;
+
+        // Take the start of a composition which starts with a call
+        let comps = run(query.clone(), config());
+        let comp = comps
+            .iter()
+            .find(|c| {
+                c.links
+                    .first()
+                    .map_or(false, |(idx, _)| layout.links[*idx].is_call())
+            })
+            .unwrap();
+        let (first_link_idx, first_label) = &comp.links[0];
+        let prefix = format!(
+            "{}{}{}{}",
+            layout.starts[comp.start_idx].label,
+            comp.start_node_label,
+            layout.links[*first_link_idx].display_name,
+            first_label
+        );
+
+        let mut prefix_query = query.clone();
+        prefix_query.comp_prefix = Some(prefix.clone());
+        let prefix_comps = run(prefix_query, config());
+        assert!(!prefix_comps.is_empty());
+        for c in &prefix_comps {
+            assert!(c.display_string(layout).starts_with(&prefix));
+        }
+
+        let mut bad_query = query.clone();
+        bad_query.comp_prefix = Some("not a call string".to_owned());
+        assert!(matches!(
+            crate::run_query(Arc::new(bad_query), &mut config(), None),
+            Err(QueryError::PrefixNotFound(_))
+        ));
+    }
+}
@@ -42,6 +42,9 @@ pub struct Layout {
     /// (because every possible starting point is also an end point).  The only exceptions to this
     /// are cases where e.g. snap finishes are allowed but snap starts are not.
     pub ends: EndVec<StartOrEnd>,
+    /// The lead length of the method in each block.  Lead heads occur at every multiple of this
+    /// from the start of the block.
+    pub lead_lens: BlockVec<usize>,
     pub stage: Stage,
 }
 
@@ -99,9 +102,9 @@ impl Layout {
 /// A link between two segments of a course
 #[derive(Debug, Clone)]
 pub struct Link {
-    /// Which [`Row`] in the [`Layout`] this `Link` starts from.  This is a half-open bound - for
+    /// Which [`Row`] in the [`Layout`] this `Link` starts from.  This is an inclusive bound - for
     /// example, if this `Link` represents a call over the lead end then this index refers to the
-    /// lead **head**, not the lead **end**.
+    /// lead **end**, not the lead **head**.
     pub from: RowIdx,
     /// Which [`Row`] the composition will be at after this `Link` is taken
     pub to: RowIdx,
@@ -125,6 +128,11 @@ impl Link {
         !self.ch_transposition.is_rounds()
     }
 
+    /// Returns `true` if taking this `Link` changes method (i.e. it moves to a different block)
+    pub fn is_splice(&self) -> bool {
+        self.from.block != self.to.block
+    }
+
     /// Returns `true` if `self` and `other` are equal (but ignoring the name and CH masks)
     fn eq_without_name_or_ch_mask(&self, other: &Self) -> bool {
         self.from == other.from
@@ -14,6 +14,7 @@ use crate::layout::{BlockIdx, BlockVec, Layout, Link, RowIdx, StartOrEnd};
 
 /// Helper function to generate a [`Layout`] from human-friendly inputs (i.e. what [`Method`]s,
 /// [`Call`](super::Call)s and course heads to use).
+#[allow(clippy::too_many_arguments)]
 pub fn coursewise(
     methods: &[(Method, String)],
     calls: &[super::Call],
@@ -26,6 +27,9 @@ pub fn coursewise(
     // composition.  If these are `None`, then any location is allowed
     allowed_start_indices: Option<&[usize]>,
     allowed_end_indices: Option<&[usize]>,
+    // The rows at which the composition starts and finishes (usually rounds)
+    start_row: &Row,
+    end_row: &Row,
 ) -> Result<Layout> {
     // Cache data about each method, and compute the overall stage of the comp
     check_duplicate_shorthand(methods)?;
@@ -45,18 +49,19 @@ pub fn coursewise(
 
     Ok(Layout {
         links,
-        starts: rounds_locations(
+        starts: row_locations(
             &method_datas,
-            stage,
+            start_row,
             allowed_start_indices,
             super::SNAP_START_LABEL,
         ),
-        ends: rounds_locations(
+        ends: row_locations(
             &method_datas,
-            stage,
+            end_row,
             allowed_end_indices,
             super::SNAP_FINISH_LABEL,
         ),
+        lead_lens: method_datas.iter().map(|d| d.method.lead_len()).collect(),
         // Create a block for each method
         blocks: method_datas
             .into_iter()
@@ -615,30 +620,31 @@ fn filter_plain_links(links: &mut Vec<Link>, splice_style: SpliceStyle) {
 // STARTS/ENDS //
 /////////////////
 
-fn rounds_locations<I: index_vec::Idx>(
+/// Find every location in the course layout where `target_row` can be rung (usually rounds),
+/// along with the course head which generates it.
+fn row_locations<I: index_vec::Idx>(
     method_datas: &[MethodData],
-    stage: Stage,
+    target_row: &Row,
     allowed_sub_lead_indices: Option<&[usize]>,
     snap_label: &str,
 ) -> IndexVec<I, StartOrEnd> {
-    let rounds = RowBuf::rounds(stage);
-
     let mut positions = IndexVec::new();
     for (method_idx, d) in method_datas.iter().enumerate() {
         for ch_mask in &d.ch_masks {
             for (row_idx, annot_row) in d.plain_course.annot_rows().enumerate() {
                 let transposed_mask = ch_mask.mask().mul(annot_row.row());
-                // If rounds satisfies `transposed_mask`, then this location can contain rounds
-                if transposed_mask.matches(&rounds) {
+                // If `target_row` satisfies `transposed_mask`, then this location can contain
+                // `target_row`
+                if transposed_mask.matches(target_row) {
                     // Decide whether this is snap start/finish
                     let sub_lead_index = annot_row.annot().sub_lead_idx();
                     let is_snap = sub_lead_index != 0;
-                    let course_head_containing_rounds = annot_row.row().inv();
+                    let course_head_containing_row = target_row * annot_row.row().inv().as_row();
 
                     if allowed_sub_lead_indices.map_or(true, |idxs| idxs.contains(&sub_lead_index))
                     {
                         positions.push(StartOrEnd {
-                            course_head: course_head_containing_rounds.clone(),
+                            course_head: course_head_containing_row,
                             row_idx: RowIdx::new(BlockIdx::new(method_idx), row_idx),
                             label: (if is_snap { snap_label } else { "" }).to_owned(),
                         });
@@ -13,6 +13,9 @@ pub fn leadwise(
     calls: &[super::Call],
     start_indices: Option<&[usize]>,
     end_indices: Option<&[usize]>,
+    // The rows at which the composition starts and finishes (usually rounds)
+    start_row: &Row,
+    end_row: &Row,
 ) -> Result<Layout> {
     check_duplicate_shorthand(methods)?;
 
@@ -41,15 +44,31 @@ pub fn leadwise(
 
     let blks = blocks.as_raw_slice();
     Ok(Layout {
-        starts: start_or_ends(start_indices, SNAP_START_LABEL, &lead_head_mask, blks),
-        ends: start_or_ends(end_indices, SNAP_FINISH_LABEL, &lead_head_mask, blks),
+        starts: start_or_ends(
+            start_row,
+            start_indices,
+            SNAP_START_LABEL,
+            &lead_head_mask,
+            blks,
+        ),
+        ends: start_or_ends(
+            end_row,
+            end_indices,
+            SNAP_FINISH_LABEL,
+            &lead_head_mask,
+            blks,
+        ),
         links: links(methods, calls, &lead_head_mask),
+        lead_lens: methods.iter().map(|(m, _)| m.lead_len()).collect(),
         blocks,
         stage,
     })
 }
 
+/// Find the locations where `target_row` (usually rounds) can be rung, along with the lead head
+/// which generates it
 fn start_or_ends<I: index_vec::Idx>(
+    target_row: &Row,
     allowed_indices: Option<&[usize]>,
     snap_label: &str,
     lead_head_mask: &Mask,
@@ -61,7 +80,7 @@ fn start_or_ends<I: index_vec::Idx>(
         // Closure to construct a `StartOrEnd` at a given row
         let new_start_or_end = |(row_idx, row): (usize, &Row)| -> StartOrEnd {
             StartOrEnd {
-                course_head: !row,
+                course_head: target_row * row.inv().as_row(),
                 row_idx: RowIdx::new(block_idx, row_idx),
                 label: if row_idx == 0 {
                     String::new()
@@ -78,15 +97,15 @@ fn start_or_ends<I: index_vec::Idx>(
                 assert!(idx < first_lead.len());
                 new_start_or_end((idx, &first_lead.row_vec()[idx]))
             })),
-            // If no indices are specified, then we allow any index which satisfies the
-            // lead_head_mask (i.e. any lead index where the fixed bells are at their home
-            // positions).
+            // If no indices are specified, then we allow any index where the lead head
+            // containing `target_row` satisfies the lead_head_mask (i.e. any lead index where the
+            // fixed bells are at their home positions).
             None => locs.extend(
                 first_lead
                     .rows()
                     .enumerate()
-                    .filter(|(_, r)| lead_head_mask.matches(r))
-                    .map(new_start_or_end),
+                    .map(new_start_or_end)
+                    .filter(|start_or_end| lead_head_mask.matches(&start_or_end.course_head)),
             ),
         }
     }
@@ -417,6 +417,19 @@ mod tests {
     use bellframe::{PlaceNot, Stage};
     use itertools::Itertools;
 
+    use crate::test_utils::{config, layout, plain_bob, query, run};
+
+    #[test]
+    fn start_and_end_rows() {
+        // `654321` is the 6th row of the plain course, so can be reached by a 6-row snap finish
+        let query = query(
+            layout(&[plain_bob(Stage::MINOR)], "123456", "654321"),
+            0..13,
+        );
+        let comps = run(query, config());
+        assert!(comps.iter().any(|c| c.length == 6));
+    }
+
     fn char_vec(string: &str) -> Vec<String> {
         string.chars().map(|c| c.to_string()).collect_vec()
     }
@@ -2,20 +2,25 @@ pub mod graph;
 pub mod layout;
 pub mod music;
 mod search;
+#[cfg(test)]
+mod test_utils;
 mod utils;
 
 use itertools::Itertools;
 use layout::{node_range::End, Layout, LinkIdx, Rotation, StartIdx};
-use music::Score;
+use music::{Breakdown, Score, StrokeSet};
+pub use search::FalsenessRepr;
 pub use utils::OptRange;
 use utils::RowCounts;
 
 use std::{
+    io,
     ops::Range,
+    path::PathBuf,
     sync::{Arc, Mutex},
 };
 
-use bellframe::RowBuf;
+use bellframe::{music::Regex, Mask, RowBuf};
 use graph::{optimise::Pass, Graph};
 use log::log;
 
@@ -26,13 +31,47 @@ use log::log;
 #[derive(Debug, Clone)]
 pub struct Query {
     pub layout: layout::Layout,
+    /// The part head of the composition.  Every part is assumed to start at the same stroke, so
+    /// multi-part compositions with an odd number of rows in each part are never generated.
     pub part_head: RowBuf,
     pub len_range: Range<usize>,
     pub num_comps: usize,
+    /// If set, compositions are rejected if they share more than this fraction of their links
+    /// with a composition which has already been generated (see [`Comp::overlap`]).  This makes
+    /// sure that the [`Query::num_comps`] compositions are genuinely different to each other.
+    pub max_overlap: Option<f32>,
 
     pub method_count_range: Range<usize>,
+    /// Bounds on the number of changes of method (i.e. links which switch to a different method).
+    /// Each change of method is counted once per part.
+    pub changes_of_method: OptRange,
+    /// The score given to every change of method
+    pub splice_weight: f32,
+    /// If `true`, only generate compositions which are all-the-work (i.e. every working bell
+    /// rings every working place bell of every method)
+    pub atw: bool,
+    /// The score given for every new place bell rung, used to guide the search towards ATW
+    pub atw_weight: f32,
+    /// Weights applied to every **row** in a course whose course head matches the corresponding
+    /// [`Mask`].  A row's weight is the sum of the weights of every matching mask.  Longer
+    /// stretches of a course get more weight, and the weight doesn't depend on how many times
+    /// that course is entered.
+    pub course_head_row_weights: Vec<(Mask, f32)>,
     pub music_types: Vec<music::MusicType>,
     pub max_duffer_rows: Option<usize>,
+    /// Patterns of rows which must all be included in every composition (each at one of the
+    /// given [`StrokeSet`]).  There can be at most 64 required rows.
+    pub required_rows: Vec<(Regex, StrokeSet)>,
+    /// Patterns of rows which can't be included in any composition at the given [`StrokeSet`]
+    pub forbidden_rows: Vec<(Regex, StrokeSet)>,
+    /// Bounds on the number of courses whose course heads match any of a set of [`Mask`]s.  A
+    /// course is counted every time the composition moves to a new course head (i.e. at the start
+    /// of the composition or after a call), in every part.  This only makes sense for course-wise
+    /// [`Layout`]s: in lead-wise layouts every lead is a new 'course'.
+    pub course_head_counts: Vec<(Vec<Mask>, OptRange)>,
+    /// If set, every composition must start with this call string (in the same format as
+    /// [`Comp::display_string`], but without an end label).
+    pub comp_prefix: Option<String>,
 }
 
 /// Configuration parameters for Monument which **don't** change which compositions are emitted.
@@ -41,7 +80,29 @@ pub struct Config {
     /// **physical** CPU cores (i.e. ignoring hyper-threading).
     pub num_threads: Option<usize>,
     pub queue_limit: usize,
+    /// If set, the approximate maximum number of bytes which the search's frontier can use.  The
+    /// size of each prefix is estimated from the [`Graph`], and the frontier is truncated early
+    /// (i.e. before it reaches [`Config::queue_limit`]) to stay within this budget.
+    pub memory_limit: Option<usize>,
+    /// If set, then [`Strategy::BestFirst`] will write the worst prefixes to temporary files in
+    /// this directory (rather than discarding them) whenever the frontier reaches
+    /// [`Config::queue_limit`].  This makes large searches complete rather than lossy, at the
+    /// cost of disk space and speed.  The directory is created if it doesn't exist.
+    pub spill_dir: Option<PathBuf>,
     pub optimisation_passes: Vec<Pass>,
+    /// If `true`, the search will only stop once it has proved that it has found the best
+    /// [`Query::num_comps`] compositions.  This is only feasible for small searches, and is only
+    /// used by [`Strategy::BestFirst`] ([`Strategy::DepthFirst`] is always exact).
+    pub exact: bool,
+    /// How the search decides which composition prefix to expand next
+    pub priority: Priority,
+    /// The algorithm used to search the composition graph
+    pub strategy: Strategy,
+    /// How composition prefixes which reach the same search state are merged
+    pub transposition: Transposition,
+    /// How the falseness of each composition prefix is stored.  If `None`, this is chosen
+    /// according to the size and falseness of the [`Graph`].
+    pub falseness: Option<FalsenessRepr>,
 }
 
 impl Default for Config {
@@ -49,11 +110,79 @@ impl Default for Config {
         Self {
             num_threads: None,
             queue_limit: 10_000_000,
+            memory_limit: None,
+            spill_dir: None,
             optimisation_passes: graph::optimise::passes::default(),
+            exact: false,
+            priority: Priority::AvgScore,
+            strategy: Strategy::BestFirst,
+            transposition: Transposition::Off,
+            falseness: None,
         }
     }
 }
 
+/// The algorithm used to search the composition graph for compositions
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Strategy {
+    /// Repeatedly expand the prefix with the highest [`Priority`], discarding the worst half of
+    /// the prefixes whenever there are more than [`Config::queue_limit`] of them
+    BestFirst,
+    /// Depth-first branch-and-bound, which uses very little memory and always finds the best
+    /// compositions (but may take a very long time to do so)
+    DepthFirst,
+    /// Generate _every_ valid composition (in no particular order), ignoring
+    /// [`Config::queue_limit`] and [`Query::num_comps`].  This is useful for counting or
+    /// collecting touches, but is only feasible for small searches.
+    Enumerate,
+    /// Extend every prefix by one node at a time, only keeping the best `width` prefixes
+    Beam { width: usize },
+    /// Repeatedly generate compositions by walking randomly through the graph (biased towards
+    /// prefixes with higher [`Priority`]), until `num_restarts` walks have been made
+    RandomRestarts { num_restarts: usize, seed: u64 },
+}
+
+/// How the search detects composition prefixes which reach the same state (i.e. the same node,
+/// with the same falseness, rotation, method counts, etc.).  Such prefixes can be extended in
+/// exactly the same ways, so only the best-scoring one needs to be expanded.  This is ignored by
+/// [`Strategy::Enumerate`] and [`Strategy::RandomRestarts`].
+///
+/// The states are stored in a table which is cleared whenever it holds as many states as the
+/// frontier can hold prefixes.  The table's memory is counted towards [`Config::memory_limit`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Transposition {
+    /// Expand every prefix, even if an equivalent prefix has already been expanded
+    Off,
+    /// Only expand a prefix if no prefix with the same state and length has a higher score
+    Exact,
+    /// Like [`Transposition::Exact`], but prefixes are also dropped if there's a prefix in the
+    /// same state (ignoring length) which is no longer and has at least as high a score.  This
+    /// merges more prefixes, but can lose compositions which need the longer prefix to reach the
+    /// minimum length.
+    ///
+    /// A shorter prefix only has a higher average score than a longer one if no extension can
+    /// decrease the score, so this falls back to [`Transposition::Exact`] if any node, link or
+    /// place bell has a negative score (e.g. calls with negative weights).
+    Dominance,
+}
+
+/// The value used to order composition prefixes during the search.  The prefix with the highest
+/// value is always expanded first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Priority {
+    /// The average score per row of the prefix.  This greedily expands the prefixes which are
+    /// best so far, regardless of what can be reached afterwards.
+    AvgScore,
+    /// An estimate of the average score of the best composition starting with the prefix,
+    /// assuming that the rest of the composition generates the score per row estimated by
+    /// [`passes::compute_score_estimates`](graph::optimise::passes::compute_score_estimates).
+    Estimated,
+    /// An upper bound on the average score of any composition starting with the prefix.  This
+    /// expands many more prefixes than the other priorities, but makes [`Config::exact`] searches
+    /// prune much more effectively.
+    UpperBound,
+}
+
 /// A `Comp`osition generated by Monument.
 #[derive(Debug, Clone)]
 pub struct Comp {
@@ -65,10 +194,15 @@ pub struct Comp {
     pub rotation: Rotation,
     pub length: usize,
     pub method_counts: RowCounts,
+    /// The number of changes of method in this composition (counting every part)
+    pub changes_of_method: usize,
     pub score: Score,
     /// Average [`Score`] generated by each row in the composition.   This is used to rank
     /// compositions to prevent the search algorithm being dominated by long compositions.
     pub avg_score: Score,
+    /// The music generated by this composition, with counts for each [`MusicType`](music::MusicType) in
+    /// [`Query::music_types`]
+    pub music: Breakdown,
 }
 
 impl Comp {
@@ -87,11 +221,36 @@ impl Comp {
         s
     }
 
+    /// The fraction of links (paired with the labels of the nodes they lead to) which are shared
+    /// between `self` and `other`.  This is `1.0` for identical compositions and `0.0` for
+    /// compositions which have no links in common.
+    pub fn overlap(&self, other: &Comp) -> f32 {
+        let max_len = self.links.len().max(other.links.len());
+        if max_len == 0 {
+            return 1.0; // Two compositions with no links are identical
+        }
+        // Count the links in `self`, then remove any which also appear in `other`
+        let mut link_counts = self.links.iter().counts();
+        let mut num_shared = 0;
+        for link in &other.links {
+            match link_counts.get_mut(link) {
+                Some(count) if *count > 0 => {
+                    *count -= 1;
+                    num_shared += 1;
+                }
+                _ => {}
+            }
+        }
+        num_shared as f32 / max_len as f32
+    }
+
     pub fn long_string(&self, layout: &Layout) {
         println!(
-            "len: {}, ms: {:>3?}, score: {:>6.2}, avg: {:.6}, rot: {}, str: {}",
+            "len: {}, ms: {:>3?}, com: {}, music: {:>3?}, score: {:>6.2}, avg: {:.6}, rot: {}, str: {}",
             self.length,
             self.method_counts.counts(),
+            self.changes_of_method,
+            self.music.counts,
             self.score,
             self.avg_score,
             self.rotation,
@@ -104,16 +263,48 @@ impl Comp {
 // SEARCH //
 ////////////
 
+/// The reasons that [`run_query`] can stop without running a search
+#[derive(Debug)]
+pub enum QueryError {
+    /// The caller asked for the unoptimised [`Graph`] (see [`DebugOutput::Graph`])
+    Graph(Graph),
+    /// The caller asked to stop before the search (see [`DebugOutput::StopBeforeSearch`])
+    StoppedEarly,
+    /// The [`Query::comp_prefix`] can't be rung from any of the start nodes, so no compositions
+    /// could be generated
+    PrefixNotFound(String),
+    /// [`Query::atw`] is set, but this [`PlaceBell`](graph::PlaceBell) can't be rung in full by
+    /// any part of the [`Layout`] (e.g. because calls or splices split up its lead)
+    UnrungPlaceBell(graph::PlaceBell),
+    /// The [`Config::spill_dir`] couldn't be created or isn't writable
+    SpillDir(PathBuf, io::Error),
+}
+
 /// Run a query
 pub fn run_query(
     query_arc: Arc<Query>,
     config: &mut Config,
     debug_output: Option<DebugOutput>,
-) -> Result<Vec<Comp>, Option<Graph>> {
+) -> Result<Vec<Comp>, QueryError> {
+    if let Some(dir) = &config.spill_dir {
+        search::prepare_spill_dir(dir).map_err(|e| QueryError::SpillDir(dir.clone(), e))?;
+    }
+
     log::info!("Building `Graph`");
     let mut graph = query_arc.unoptimised_graph();
     if debug_output == Some(DebugOutput::Graph) {
-        return Err(Some(graph)); // Return the graph if the caller wants to inspect it
+        return Err(QueryError::Graph(graph)); // Return the graph if the caller wants to inspect it
+    }
+    // A prefix which can't be rung (e.g. because of a typo) would remove every start node
+    if let Some(prefix) = &query_arc.comp_prefix {
+        if graph.parse_prefix(prefix, &query_arc.layout).is_empty() {
+            return Err(QueryError::PrefixNotFound(prefix.clone()));
+        }
+    }
+    if query_arc.atw {
+        if let Some(place_bell) = graph.unrung_place_bell() {
+            return Err(QueryError::UnrungPlaceBell(place_bell));
+        }
     }
 
     log::debug!("Optimising graph");
@@ -126,26 +317,68 @@ pub fn run_query(
     );
 
     if debug_output == Some(DebugOutput::StopBeforeSearch) {
-        return Err(None); // Stop early if the caller requested that
+        return Err(QueryError::StoppedEarly); // Stop early if the caller requested that
     }
 
     log::info!("Starting tree search");
     let comps_arc = Arc::from(Mutex::new(Vec::<Comp>::new()));
-    let graph_arc = Arc::from(graph);
+    // Lower the hash-based graph into a graph that's immutable but faster to traverse.  This is
+    // shared between all the threads, so that they can share prefixes
+    let graph_arc = Arc::from(search::Graph::new(&graph, &query_arc, config.falseness));
     let num_threads = config.num_threads.unwrap_or_else(num_cpus::get_physical);
     let queue_limit = config.queue_limit;
+    let exact = config.exact;
+    let priority = config.priority;
+    let strategy = config.strategy;
+    let transposition = match config.transposition {
+        Transposition::Dominance if graph_arc.has_negative_scores(&query_arc) => {
+            log::warn!("Some scores are negative, so using exact transpositions not dominance");
+            Transposition::Exact
+        }
+        t => t,
+    };
+    let memory_limit = config.memory_limit;
+    let spill_dir = config.spill_dir.clone();
+    let max_overlap = query_arc.max_overlap;
+    let pool = Arc::new(search::WorkPool::new(num_threads, &query_arc));
 
     let handles = (0..num_threads)
-        .map(|_i| {
+        .map(|thread_idx| {
             let query = query_arc.clone();
             let graph = graph_arc.clone();
             let comps = comps_arc.clone();
+            let spill_dir = spill_dir.clone();
+            let pool = pool.clone();
             std::thread::spawn(move || {
                 let on_find_comp = |c: Comp| {
+                    let mut comps = comps.lock().unwrap();
+                    // Reject comps which are too similar to ones which have already been found
+                    if let Some(max_overlap) = max_overlap {
+                        if comps.iter().any(|c2| c.overlap(c2) > max_overlap) {
+                            return false;
+                        }
+                    }
                     c.long_string(&query.layout);
-                    comps.lock().unwrap().push(c);
+                    comps.push(c);
+                    true
                 };
-                search::search(&graph, &query, queue_limit / num_threads, on_find_comp);
+                let mut strategy = search::strategy::new(
+                    strategy,
+                    queue_limit / num_threads,
+                    memory_limit.map(|m| m / num_threads),
+                    exact,
+                    spill_dir,
+                    thread_idx,
+                    pool,
+                );
+                search::search(
+                    &graph,
+                    &query,
+                    &mut *strategy,
+                    priority,
+                    transposition,
+                    on_find_comp,
+                );
             })
         })
         .collect_vec();
@@ -160,6 +393,21 @@ pub fn run_query(
     Ok(comps)
 }
 
+/// Search for compositions which are small edits away from an existing composition (given as a
+/// call string, e.g. from [`Comp::display_string`]).  Returns `None` if `call_string` isn't a
+/// composition which can be generated by the [`Query`], otherwise returns all the improved
+/// compositions which were found in ascending order of goodness.
+pub fn improve_comp(query: &Query, config: &mut Config, call_string: &str) -> Option<Vec<Comp>> {
+    log::info!("Building `Graph`");
+    let mut graph = query.unoptimised_graph();
+    log::debug!("Optimising graph");
+    graph.optimise(&mut config.optimisation_passes, query);
+
+    let comp = graph.parse_comp(call_string, &query.layout)?;
+    log::info!("Improving composition");
+    Some(search::improve(&graph, query, &comp))
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum DebugOutput {
     /// Return the unoptimised [`Graph`]
@@ -171,12 +419,6 @@ pub enum DebugOutput {
 
 impl Query {
     fn unoptimised_graph(&self) -> Graph {
-        graph::Graph::from_layout(
-            &self.layout,
-            &self.music_types,
-            // `- 1` makes sure that the length limit is an **inclusive** bound
-            self.len_range.end - 1,
-            &self.part_head,
-        )
+        graph::Graph::from_query(self)
     }
 }
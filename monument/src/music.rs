@@ -1,45 +1,141 @@
-use std::ops::{Add, AddAssign, Sub, SubAssign};
+use std::ops::{Add, AddAssign, Not, Sub, SubAssign};
 
 use crate::utils::OptRange;
-use bellframe::{music::Regex, Row, RowBuf, Stage};
+use bellframe::{music::Regex, Bell, Row, RowBuf, Stage};
 use itertools::Itertools;
 use ordered_float::OrderedFloat;
+use serde::Deserialize;
 
 pub type Score = OrderedFloat<f32>;
 
 /// A class of music that Monument should care about
 #[derive(Debug, Clone)]
 pub struct MusicType {
-    regexes: Vec<Regex>,
+    patterns: Patterns,
     weight: Score,
     count_range: OptRange,
     non_duffer: bool,
+    /// Which [`Stroke`]s this music is counted at
+    stroke: StrokeSet,
+}
+
+/// The patterns which define a [`MusicType`]
+#[derive(Debug, Clone)]
+pub enum Patterns {
+    /// Patterns which each match a single [`Row`]
+    Rows(Vec<Regex>),
+    /// Pairs of patterns which match two consecutive [`Row`]s (e.g. for wrapped runs).  A pair is
+    /// rung at the [`Stroke`] of its first row.  Pairs which span the join between two parts of a
+    /// multi-part are not counted.
+    RowPairs(Vec<(Regex, Regex)>),
+    /// Masks which match the coursing order of the course in which each [`Row`] is rung.  These are
+    /// counted once for every row in a matching course.
+    CoursingOrders(Vec<CoursingOrderMask>),
+}
+
+impl Patterns {
+    /// The pairs of patterns which match runs of exactly `len` bells which are split across two
+    /// consecutive [`Row`]s (i.e. the first part of the run is at the back of the first row and
+    /// the rest is at the front of the second row).  These can be used in [`Patterns::RowPairs`].
+    pub fn wrapped_runs(stage: Stage, len: usize) -> Vec<(Regex, Regex)> {
+        let num_bells = stage.num_bells();
+        let mut pairs = Vec::new();
+        for first_bell in 0..(num_bells + 1).saturating_sub(len) {
+            let ascending = (first_bell..first_bell + len)
+                .map(Bell::from_index)
+                .collect_vec();
+            let descending = ascending.iter().rev().copied().collect_vec();
+            for run in [ascending, descending] {
+                // Split the run so that both rows get at least one bell
+                for split in 1..len {
+                    let (back, front) = run.split_at(split);
+                    let back_pattern = format!("*{}", back.iter().join(""));
+                    let front_pattern = format!("{}*", front.iter().join(""));
+                    pairs.push((Regex::parse(&back_pattern), Regex::parse(&front_pattern)));
+                }
+            }
+        }
+        pairs
+    }
 }
 
 impl MusicType {
-    pub fn new(regexes: Vec<Regex>, weight: f32, count_range: OptRange, non_duffer: bool) -> Self {
+    pub fn new(
+        patterns: Patterns,
+        weight: f32,
+        count_range: OptRange,
+        non_duffer: bool,
+        stroke: StrokeSet,
+    ) -> Self {
         Self {
-            regexes,
+            patterns,
             weight: OrderedFloat(weight),
             count_range,
             non_duffer,
+            stroke,
         }
     }
 
-    /// Compute the score of a sequence of [`Row`]s
-    pub fn score<'r>(&self, rows: impl IntoIterator<Item = &'r Row>) -> Score {
+    /// Compute the score of a sequence of [`Row`]s, the first of which is rung at `start_stroke`
+    pub fn score<'r>(
+        &self,
+        rows: impl IntoIterator<Item = &'r Row>,
+        start_stroke: Stroke,
+    ) -> Score {
         let mut num_matches = 0usize;
-        for row in rows.into_iter() {
-            for regex in &self.regexes {
-                if regex.matches(row) {
-                    num_matches += 1;
-                }
+        let mut last_row: Option<&Row> = None;
+        for (i, row) in rows.into_iter().enumerate() {
+            let stroke = start_stroke.offset(i);
+            num_matches += self.count_row(row, stroke);
+            if let Some(last_row) = last_row {
+                num_matches += self.count_row_pair(last_row, row, !stroke);
             }
+            last_row = Some(row);
         }
         // Give each match a score of `self.weight`
         Score::from(num_matches as f32) * self.weight
     }
 
+    /// The number of matches of this `MusicType` in a single [`Row`], rung at `stroke`
+    fn count_row(&self, row: &Row, stroke: Stroke) -> usize {
+        match &self.patterns {
+            Patterns::Rows(regexes) if self.stroke.contains(stroke) => {
+                regexes.iter().filter(|regex| regex.matches(row)).count()
+            }
+            _ => 0,
+        }
+    }
+
+    /// The number of matches of this `MusicType` in a pair of consecutive [`Row`]s, where `first`
+    /// is rung at `first_stroke`
+    fn count_row_pair(&self, first: &Row, second: &Row, first_stroke: Stroke) -> usize {
+        match &self.patterns {
+            Patterns::RowPairs(pairs) if self.stroke.contains(first_stroke) => pairs
+                .iter()
+                .filter(|(regex1, regex2)| regex1.matches(first) && regex2.matches(second))
+                .count(),
+            _ => 0,
+        }
+    }
+
+    /// The number of times this `MusicType` matches every row in a course with a given course
+    /// head (ignoring strokes)
+    fn count_course_head(&self, course_head: &Row) -> usize {
+        match &self.patterns {
+            Patterns::CoursingOrders(masks) => {
+                let coursing_order = coursing_order(course_head);
+                masks.iter().filter(|m| m.matches(&coursing_order)).count()
+            }
+            _ => 0,
+        }
+    }
+
+    /// Returns `true` if this `MusicType` can match across more than one [`Row`] (and therefore
+    /// across the joins between nodes)
+    pub fn is_multi_row(&self) -> bool {
+        matches!(self.patterns, Patterns::RowPairs(_))
+    }
+
     pub fn count_range(&self) -> OptRange {
         self.count_range
     }
@@ -47,10 +143,199 @@ impl MusicType {
     pub fn non_duffer(&self) -> bool {
         self.non_duffer
     }
+
+    pub fn stroke(&self) -> StrokeSet {
+        self.stroke
+    }
+}
+
+/////////////////////
+// COURSING ORDERS //
+/////////////////////
+
+/// A mask over the coursing order of a course, where some bells are fixed and the rest can be
+/// anything (written `x`).  Coursing orders are written starting from the bell after the tenor,
+/// without the treble or tenor (so `753246` is the plain coursing order on Major).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CoursingOrderMask {
+    bells: Vec<Option<Bell>>,
+}
+
+impl CoursingOrderMask {
+    /// Parse a `CoursingOrderMask` from a string (e.g. `x5xx6x`), returning `None` if any
+    /// character isn't either `x` or a valid bell name.
+    pub fn parse(s: &str) -> Option<Self> {
+        let bells = s
+            .chars()
+            .filter(|c| !c.is_whitespace())
+            .map(|c| match c {
+                'x' | 'X' => Some(None),
+                _ => Bell::from_name(c).map(Some),
+            })
+            .collect::<Option<Vec<_>>>()?;
+        Some(Self { bells })
+    }
+
+    /// The number of bells in the coursing orders matched by this mask
+    pub fn len(&self) -> usize {
+        self.bells.len()
+    }
+
+    /// Returns `true` if this mask matches no bells
+    pub fn is_empty(&self) -> bool {
+        self.bells.is_empty()
+    }
+
+    fn matches(&self, coursing_order: &[Bell]) -> bool {
+        self.bells.len() == coursing_order.len()
+            && self
+                .bells
+                .iter()
+                .zip_eq(coursing_order)
+                .all(|(mask_bell, bell)| mask_bell.map_or(true, |b| b == *bell))
+    }
+}
+
+/// Compute the coursing order of a course head, starting from the bell after the tenor and
+/// excluding the tenor.  The treble (or whichever bell is leading) is also excluded.  If the tenor
+/// is leading, this returns an empty coursing order.
+pub fn coursing_order(course_head: &Row) -> Vec<Bell> {
+    let bells = course_head.bell_iter().collect_vec();
+    let num_bells = bells.len();
+    // The places (0-indexed) in the order that they course: ascending even places, followed by
+    // descending odd places (excluding lead)
+    let cycle = (1..num_bells)
+        .step_by(2)
+        .chain((2..num_bells).step_by(2).rev())
+        .map(|place| bells[place])
+        .collect_vec();
+    let tenor = Bell::tenor(course_head.stage());
+    match cycle.iter().position(|b| *b == tenor) {
+        Some(tenor_idx) => cycle[tenor_idx + 1..]
+            .iter()
+            .chain(&cycle[..tenor_idx])
+            .copied()
+            .collect_vec(),
+        None => Vec::new(),
+    }
+}
+
+/////////////
+// STROKES //
+/////////////
+
+/// The stroke (handstroke or backstroke) at which a [`Row`] is rung
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Stroke {
+    Hand,
+    Back,
+}
+
+impl Stroke {
+    /// The `Stroke` of the row at a given (0-indexed) position in a part of the composition.  The
+    /// first row (usually rounds) is a backstroke, so the first change is rung at handstroke.
+    ///
+    /// Note that this assumes that parts have an even length (which is why multi-part
+    /// compositions with odd-length parts are rejected).
+    pub fn of_row(idx: usize) -> Self {
+        Self::Back.offset(idx)
+    }
+
+    /// The `Stroke` of the row `num_rows` rows after a row rung at `self`
+    pub fn offset(self, num_rows: usize) -> Self {
+        if num_rows % 2 == 0 {
+            self
+        } else {
+            !self
+        }
+    }
+}
+
+impl Not for Stroke {
+    type Output = Stroke;
+
+    fn not(self) -> Self::Output {
+        match self {
+            Stroke::Hand => Stroke::Back,
+            Stroke::Back => Stroke::Hand,
+        }
+    }
+}
+
+/// A set of [`Stroke`]s, specifying where a [`MusicType`] should be counted
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StrokeSet {
+    Hand,
+    Back,
+    Both,
+}
+
+impl StrokeSet {
+    pub fn contains(self, stroke: Stroke) -> bool {
+        match self {
+            StrokeSet::Hand => stroke == Stroke::Hand,
+            StrokeSet::Back => stroke == Stroke::Back,
+            StrokeSet::Both => true,
+        }
+    }
+}
+
+impl Default for StrokeSet {
+    fn default() -> Self {
+        Self::Both
+    }
+}
+
+/// A value for each [`Stroke`].  Nodes can start at either stroke, so this is used to store
+/// e.g. the music generated by a node in both cases.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct PerStroke<T> {
+    pub hand: T,
+    pub back: T,
 }
 
+impl<T> PerStroke<T> {
+    pub fn new(hand: T, back: T) -> Self {
+        Self { hand, back }
+    }
+
+    /// Create a `PerStroke` by computing the value for each [`Stroke`]
+    pub fn from_fn(mut f: impl FnMut(Stroke) -> T) -> Self {
+        Self {
+            hand: f(Stroke::Hand),
+            back: f(Stroke::Back),
+        }
+    }
+
+    pub fn get(&self, stroke: Stroke) -> &T {
+        match stroke {
+            Stroke::Hand => &self.hand,
+            Stroke::Back => &self.back,
+        }
+    }
+
+    pub fn get_mut(&mut self, stroke: Stroke) -> &mut T {
+        match stroke {
+            Stroke::Hand => &mut self.hand,
+            Stroke::Back => &mut self.back,
+        }
+    }
+
+    pub fn map<U>(&self, mut f: impl FnMut(&T) -> U) -> PerStroke<U> {
+        PerStroke {
+            hand: f(&self.hand),
+            back: f(&self.back),
+        }
+    }
+}
+
+///////////////
+// BREAKDOWN //
+///////////////
+
 /// A breakdown of the music generated by a composition
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct Breakdown {
     pub score: Score,
     /// The number of occurrences of each [`MusicType`] (the list of music types is stored in the
@@ -68,27 +353,66 @@ impl Breakdown {
     }
 
     /// Returns the `Score` generated by a sequence of [`Row`]s, (pre-)transposed by some course head.
+    /// The first row is rung at `start_stroke`.
     pub fn from_rows<'r>(
         rows: impl IntoIterator<Item = &'r Row>,
         course_head: &Row,
         music_types: &[MusicType],
+        start_stroke: Stroke,
     ) -> Self {
+        let mut last_row = RowBuf::rounds(Stage::ONE);
         let mut temp_row = RowBuf::rounds(Stage::ONE);
         let mut occurences = vec![0; music_types.len()];
+        // Every row has the same course head, so music which depends only on the course head is
+        // the same for every row
+        let course_head_counts = music_types
+            .iter()
+            .map(|ty| ty.count_course_head(course_head))
+            .collect_vec();
         // For every (transposed) row ...
-        for r in rows {
+        for (i, r) in rows.into_iter().enumerate() {
             course_head.mul_into(r, &mut temp_row).unwrap();
+            let stroke = start_stroke.offset(i);
             // ... for every music type ...
-            for (num_instances, ty) in occurences.iter_mut().zip_eq(music_types) {
-                // ... count the number of instances of that type of music
-                for regex in &ty.regexes {
-                    if regex.matches(&temp_row) {
-                        *num_instances += 1;
-                    }
+            for ((num_instances, ty), ch_count) in occurences
+                .iter_mut()
+                .zip_eq(music_types)
+                .zip_eq(&course_head_counts)
+            {
+                // ... count the number of instances of that type of music, either in this row, in
+                // this row and the one before, or in the course containing this row
+                *num_instances += ty.count_row(&temp_row, stroke);
+                if ty.stroke.contains(stroke) {
+                    *num_instances += ch_count;
+                }
+                if i > 0 {
+                    *num_instances += ty.count_row_pair(&last_row, &temp_row, !stroke);
                 }
             }
+            std::mem::swap(&mut last_row, &mut temp_row);
         }
 
+        Self::from_counts(occurences, music_types)
+    }
+
+    /// Returns the `Score` generated by only the join between two consecutive [`Row`]s (i.e. not
+    /// including any music generated by either row on its own).  `first` is rung at
+    /// `first_stroke`.
+    pub fn from_join(
+        first: &Row,
+        second: &Row,
+        music_types: &[MusicType],
+        first_stroke: Stroke,
+    ) -> Self {
+        let occurences = music_types
+            .iter()
+            .map(|ty| ty.count_row_pair(first, second, first_stroke))
+            .collect_vec();
+        Self::from_counts(occurences, music_types)
+    }
+
+    /// Creates a `Breakdown` from the number of occurrences of each [`MusicType`]
+    fn from_counts(occurences: Vec<usize>, music_types: &[MusicType]) -> Self {
         Self {
             score: occurences
                 .iter()
@@ -99,6 +423,24 @@ impl Breakdown {
         }
     }
 
+    /// Returns a `Breakdown` where every count (and the score) is the maximum of that in `self`
+    /// and `rhs`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the number of [`MusicType`]s in `rhs` is different to that of `self`.
+    pub fn pointwise_max(&self, rhs: &Self) -> Self {
+        Breakdown {
+            score: self.score.max(rhs.score),
+            counts: self
+                .counts
+                .iter()
+                .zip_eq(rhs.counts.iter())
+                .map(|(a, b)| *a.max(b))
+                .collect_vec(),
+        }
+    }
+
     /// # Panics
     ///
     /// Panics if the number of [`MusicType`]s in `rhs` is different to that of `self`.
@@ -196,3 +538,59 @@ impl SubAssign<&Breakdown> for Breakdown {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use bellframe::{Bell, RowBuf, Stage};
+    use itertools::Itertools;
+
+    use super::{coursing_order, CoursingOrderMask};
+
+    fn bells(s: &str) -> Vec<Bell> {
+        s.chars().map(|c| Bell::from_name(c).unwrap()).collect_vec()
+    }
+
+    #[test]
+    fn coursing_orders() {
+        #[rustfmt::skip]
+        let cases = &[
+            ("123456", Stage::MINOR, "5324"),
+            ("12345678", Stage::MAJOR, "753246"),
+            ("13527486", Stage::MAJOR, "753246"),
+            ("12436578", Stage::MAJOR, "764235"),
+            ("1234567890", Stage::ROYAL, "97532468"),
+            ("81234567", Stage::MAJOR, ""),
+        ];
+
+        for &(ch_str, stage, exp_coursing_order) in cases {
+            let course_head = RowBuf::parse_with_stage(ch_str, stage).unwrap();
+            assert_eq!(
+                coursing_order(&course_head),
+                bells(exp_coursing_order),
+                "{}",
+                ch_str
+            );
+        }
+    }
+
+    #[test]
+    fn coursing_order_masks() {
+        #[rustfmt::skip]
+        let cases = &[
+            ("753246", "753246", true),
+            ("x53246", "753246", true),
+            ("xxxxxx", "753246", true),
+            ("x5xx6x", "753264", true),
+            ("x5xx6x", "753246", false),
+            ("x5xx6x", "764235", false),
+            ("xxxxx", "753246", false),
+        ];
+
+        for &(mask, coursing_order, exp_matches) in cases {
+            let matches = CoursingOrderMask::parse(mask)
+                .unwrap()
+                .matches(&bells(coursing_order));
+            assert_eq!(matches, exp_matches, "{} {}", mask, coursing_order);
+        }
+    }
+}
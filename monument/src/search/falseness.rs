@@ -0,0 +1,181 @@
+//! Compact representations of the set of nodes which are false against a composition prefix.
+//!
+//! For small graphs, storing a bit for every node (and ORing in each new node's falseness) is very
+//! fast.  But every prefix owns its own copy of this set, so for graphs with tens of thousands of
+//! nodes the bitsets dominate both the time and memory used by the search.  In that case, it's
+//! much cheaper to store the (short) list of nodes in the prefix and check each new node against
+//! it.
+
+use std::io::{self, Read, Write};
+
+use bit_vec::BitVec;
+use log::log;
+
+use super::{
+    graph::{Graph, NodeIdx},
+    spill::{read_bit_vec, read_u32, read_u64, write_bit_vec, write_u32, write_u64},
+};
+
+/// How the falseness of each prefix is stored during a search
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FalsenessRepr {
+    /// Each prefix stores a bit for every node in the graph
+    Dense,
+    /// Each prefix stores a sorted list of the nodes it contains
+    Sparse,
+}
+
+impl FalsenessRepr {
+    /// Decide which representation to use for a graph.  `num_false_pairs` is the total size of all
+    /// the nodes' falseness, and `nodes_per_comp` is an estimate of the number of nodes in each
+    /// composition.
+    pub fn choose(num_nodes: usize, num_false_pairs: usize, nodes_per_comp: usize) -> Self {
+        // Adding a node to a dense set clones and ORs every word of the set
+        let dense_cost = (num_nodes + 63) / 64;
+        // Adding a node to a sparse set clones the list, and checking a node binary searches the
+        // list for each node which is false against it
+        let avg_falseness = num_false_pairs / num_nodes.max(1);
+        let log_nodes = (usize::BITS - nodes_per_comp.leading_zeros()) as usize;
+        let sparse_cost = nodes_per_comp + avg_falseness * log_nodes;
+
+        let repr = if sparse_cost < dense_cost {
+            Self::Sparse
+        } else {
+            Self::Dense
+        };
+        log::debug!(
+            "Using {:?} falseness ({} nodes, {:.1} false nodes per node, ~{} nodes per comp)",
+            repr,
+            num_nodes,
+            num_false_pairs as f32 / num_nodes.max(1) as f32,
+            nodes_per_comp
+        );
+        repr
+    }
+}
+
+/// The set of nodes which can't be added to a composition prefix because they're false against a
+/// node which is already in the prefix
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub(super) enum Unreachable {
+    /// A bit for every node in the graph, which is set if that node is unreachable
+    Dense(BitVec),
+    /// The sorted indices of every node in the prefix.  A node is unreachable if any of the nodes
+    /// in its [`Node::falsified_by`](super::graph::Node::falsified_by) are in this list.
+    Sparse(Vec<u32>),
+}
+
+impl Unreachable {
+    /// The set of unreachable nodes for a prefix containing only `node_idx`
+    pub(super) fn new(node_idx: NodeIdx, graph: &Graph) -> Self {
+        match graph.falseness_repr {
+            FalsenessRepr::Dense => Self::Dense(graph.nodes[node_idx].falseness.clone()),
+            FalsenessRepr::Sparse => Self::Sparse(vec![node_idx.index() as u32]),
+        }
+    }
+
+    /// An estimate of the number of bytes of heap memory used by each set
+    pub(super) fn heap_size_estimate(graph: &Graph) -> usize {
+        match graph.falseness_repr {
+            FalsenessRepr::Dense => (graph.nodes.len() + 31) / 32 * 4, // BitVecs use `u32` blocks
+            FalsenessRepr::Sparse => graph.nodes_per_comp * 4,
+        }
+    }
+
+    /// Returns `true` if `node_idx` is false against the prefix
+    pub(super) fn contains(&self, node_idx: NodeIdx, graph: &Graph) -> bool {
+        match self {
+            Self::Dense(bits) => bits.get(node_idx.index()).unwrap(),
+            Self::Sparse(nodes) => graph.nodes[node_idx]
+                .falsified_by
+                .iter()
+                .any(|idx| nodes.binary_search(idx).is_ok()),
+        }
+    }
+
+    /// The set of unreachable nodes after `node_idx` has been added to the prefix
+    pub(super) fn with_node(&self, node_idx: NodeIdx, graph: &Graph) -> Self {
+        match self {
+            Self::Dense(bits) => {
+                let mut bits = bits.clone();
+                bits.or(&graph.nodes[node_idx].falseness);
+                Self::Dense(bits)
+            }
+            Self::Sparse(nodes) => {
+                let idx = node_idx.index() as u32;
+                let mut nodes = nodes.clone();
+                if let Err(insert_idx) = nodes.binary_search(&idx) {
+                    nodes.insert(insert_idx, idx);
+                }
+                Self::Sparse(nodes)
+            }
+        }
+    }
+
+    pub(super) fn write_to(&self, w: &mut impl Write) -> io::Result<()> {
+        match self {
+            Self::Dense(bits) => {
+                w.write_all(&[0])?;
+                write_bit_vec(w, bits)
+            }
+            Self::Sparse(nodes) => {
+                w.write_all(&[1])?;
+                write_u64(w, nodes.len() as u64)?;
+                nodes.iter().try_for_each(|&idx| write_u32(w, idx))
+            }
+        }
+    }
+
+    pub(super) fn read_from(r: &mut impl Read) -> io::Result<Self> {
+        let mut tag = [0];
+        r.read_exact(&mut tag)?;
+        Ok(match tag[0] {
+            0 => Self::Dense(read_bit_vec(r)?),
+            _ => {
+                let len = read_u64(r)?;
+                let nodes = (0..len)
+                    .map(|_| read_u32(r))
+                    .collect::<io::Result<Vec<_>>>()?;
+                Self::Sparse(nodes)
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bellframe::Stage;
+
+    use crate::{
+        test_utils::{comp_strings, config, layout, plain_bob, query, run, runs},
+        Config, FalsenessRepr, Strategy,
+    };
+
+    /// Both representations store the same sets of nodes, so should generate the same
+    /// compositions.  Plain Bob Minor with singles has lots of falseness between its courses.
+    #[test]
+    fn dense_and_sparse_agree() {
+        let mut query = query(
+            layout(&[plain_bob(Stage::MINOR)], "123456", "123456"),
+            0..200,
+        );
+        query.num_comps = 30;
+        query.music_types = vec![runs(Stage::MINOR, 4)];
+
+        let comps = |falseness: FalsenessRepr| {
+            let comps = run(
+                query.clone(),
+                Config {
+                    strategy: Strategy::DepthFirst,
+                    falseness: Some(falseness),
+                    ..config()
+                },
+            );
+            let scores = comps.iter().map(|c| c.avg_score).collect::<Vec<_>>();
+            (comp_strings(&comps, &query.layout), scores)
+        };
+        let (dense_comps, dense_scores) = comps(FalsenessRepr::Dense);
+        assert_eq!(dense_comps.len(), 30);
+        assert_eq!(comps(FalsenessRepr::Sparse), (dense_comps, dense_scores));
+    }
+}
@@ -1,39 +1,88 @@
-use std::collections::HashMap;
+use std::{collections::HashMap, ops::Range};
 
 use crate::{
     layout::{node_range::End, LinkIdx, NodeId, Rotation, StartIdx},
-    music::Score,
-    utils::RowCounts,
+    music::{Breakdown, PerStroke, Score, Stroke},
     Query,
 };
 use bit_vec::BitVec;
 use itertools::Itertools;
 
+use super::falseness::FalsenessRepr;
+
 /// An immutable version of [`monument_graph::Graph`] which can be traversed without hash table
 /// lookups.
+///
+/// To make expanding nodes as cache-friendly as possible, the successor [`Link`]s of every node are
+/// stored contiguously in one [`Vec`] (i.e. in 'compressed sparse row' format).  Any data which is
+/// only needed once a composition has been found (music breakdowns and labels) is stored
+/// separately from the [`Node`]s and [`Link`]s.
 #[derive(Debug, Clone)]
 pub struct Graph {
+    pub num_parts: Rotation,
     pub starts: Vec<(NodeIdx, StartIdx, Rotation)>,
     pub nodes: NodeVec<Node>,
+    /// The successors of every node.  The successors of node `i` are
+    /// `links[succ_offsets[i]..succ_offsets[i + 1]]`.
+    links: Vec<Link>,
+    succ_offsets: Vec<u32>,
+    /// The method counts of every node, stored as a flat matrix with `num_methods` columns
+    method_counts: Vec<usize>,
+    num_methods: usize,
+
+    /// The music generated by each node, depending on the stroke of its first row
+    node_music: NodeVec<PerStroke<Breakdown>>,
+    /// The music generated by each link (indexed the same way as `links`), depending on the stroke
+    /// of the first row after it
+    link_music: Vec<PerStroke<Breakdown>>,
+    labels: NodeVec<String>,
+
+    /// An upper bound on the score generated by the 0-length node at the end of a composition
+    /// (including the link leading to it).  This is never negative.
+    pub max_end_score: f32,
+    /// Every way of ringing the [`Query::comp_prefix`] (if it exists), as start nodes followed by
+    /// the links and nodes taken after them
+    pub fixed_prefixes: Vec<(NodeIdx, StartIdx, Rotation, Vec<(LinkIdx, NodeIdx)>)>,
+    /// How prefixes store the nodes which are false against them.  This determines which of
+    /// [`Node::falseness`] and [`Node::falsified_by`] are populated.
+    pub falseness_repr: FalsenessRepr,
+    /// An estimate of the number of nodes in each composition
+    pub nodes_per_comp: usize,
 }
 
 #[derive(Debug, Clone)]
 pub struct Node {
-    pub score: Score,
+    /// The score of this node, depending on the stroke of its first row
+    pub score: PerStroke<Score>,
     pub length: u32,
-    pub method_counts: RowCounts,
     /// Minimum number of rows required to go from the end of `self` to rounds
     pub dist_to_rounds: u32,
-    pub label: String,
+    /// Maximum score per row generated by any node (and the link leading to it) which can be rung
+    /// after `self`
+    pub max_score_per_row: f32,
+    /// Estimate of the score per row generated between `self` and rounds, if it's been computed
+    pub est_score_per_row: Option<f32>,
 
     pub duffer: bool,
     pub dist_to_non_duffer: u32,
 
-    // Indices must be aligned with those from the source graph
-    pub succs: Vec<Link>,
     // If this node is added to a composition, these bits denote the set of nodes will be marked as
-    // unreachable.  This includes `Self`
+    // unreachable.  This includes `Self`.  Only used for `FalsenessRepr::Dense` (otherwise empty)
     pub falseness: BitVec,
+    /// The (sorted) indices of every node which is false against this node, including `self`.
+    /// Only used for [`FalsenessRepr::Sparse`] (otherwise empty).
+    pub falsified_by: Vec<u32>,
+    /// The place bells rung by this node, indexed the same way as [`Graph::place_bells`].  This
+    /// is empty if the [`Query`] doesn't care about ATW.
+    pub place_bells: BitVec,
+    /// Does this node contain a forbidden row, depending on the stroke of its first row?
+    pub forbidden: PerStroke<bool>,
+    /// Bitmask of the required rows contained in this node, depending on the stroke of its first
+    /// row
+    pub required_rows: PerStroke<u64>,
+    /// For each of the [`Query::course_head_counts`], how many times this node's course is counted
+    /// (i.e. how many parts in which its course head matches)
+    pub course_head_matches: Vec<u32>,
 
     pub end: Option<End>,
 }
@@ -41,19 +90,33 @@ pub struct Node {
 /// A link between a node and its successor
 #[derive(Debug, Clone)]
 pub struct Link {
-    pub score: Score,
+    /// The score of taking this link, depending on the stroke of the first row after it
+    pub score: PerStroke<Score>,
     pub source_idx: LinkIdx,
     pub next_node: NodeIdx,
     pub rot: Rotation,
+    /// Does taking this link change method?
+    pub is_splice: bool,
+    /// Does taking this link change course head?
+    pub is_call: bool,
 }
 
 impl Link {
-    pub fn new(score: f32, source_idx: LinkIdx, next_node: NodeIdx, rot: Rotation) -> Self {
+    pub fn new(
+        score: PerStroke<Score>,
+        source_idx: LinkIdx,
+        next_node: NodeIdx,
+        rot: Rotation,
+        is_splice: bool,
+        is_call: bool,
+    ) -> Self {
         Self {
-            score: Score::from(score),
+            score,
             source_idx,
             next_node,
             rot,
+            is_splice,
+            is_call,
         }
     }
 }
@@ -63,7 +126,13 @@ impl Link {
 ///////////////////////////////////////////
 
 impl Graph {
-    pub fn new(source_graph: &crate::graph::Graph, query: &Query) -> Self {
+    /// Lower a [`Graph`](crate::graph::Graph), storing falseness in the given [`FalsenessRepr`]
+    /// (or choosing one if `falseness` is `None`)
+    pub fn new(
+        source_graph: &crate::graph::Graph,
+        query: &Query,
+        falseness: Option<FalsenessRepr>,
+    ) -> Self {
         let num_nodes = source_graph.node_map().len();
 
         // Assign each node ID to a unique `NodeIdx`, and vice versa.  This way, we can now label
@@ -76,7 +145,62 @@ impl Graph {
             id_to_index.insert(id.to_owned(), index);
         }
 
-        // Now convert nodes from `monument_graph::Node` to `self::Node`
+        // Likewise, give each place bell an index so that place bells can be stored in `BitVec`s.
+        // If we don't care about ATW, then we don't track any place bells.
+        let place_bell_indices = if query.atw || query.atw_weight != 0.0 {
+            source_graph
+                .place_bells()
+                .iter()
+                .enumerate()
+                .map(|(idx, place_bell)| (*place_bell, idx))
+                .collect::<HashMap<_, _>>()
+        } else {
+            HashMap::new()
+        };
+
+        // Find the nodes which are false against each node, and use them to decide how falseness
+        // should be represented during the search
+        let false_idxs: NodeVec<Vec<NodeIdx>> = index_to_id
+            .iter()
+            .map(|(_id, source_node)| {
+                source_node
+                    .false_nodes()
+                    .iter()
+                    .map(|false_std_id| id_to_index[&NodeId::Standard(false_std_id.clone())])
+                    .collect_vec()
+            })
+            .collect();
+        let num_false_pairs = false_idxs.iter().map(Vec::len).sum::<usize>();
+        let (num_rows, num_nonempty_nodes) = index_to_id
+            .iter()
+            .map(|(_id, source_node)| source_node.length())
+            .filter(|&len| len > 0)
+            .fold((0, 0), |(rows, nodes), len| (rows + len, nodes + 1));
+        let nodes_per_comp = query.len_range.end * num_nonempty_nodes / num_rows.max(1);
+        let falseness_repr = falseness
+            .unwrap_or_else(|| FalsenessRepr::choose(num_nodes, num_false_pairs, nodes_per_comp));
+
+        // Sparse falseness checks each new node against the nodes whose falseness contains it, so
+        // we need to invert the falseness lists
+        let mut falsified_by = NodeVec::<Vec<u32>>::from_vec(vec![Vec::new(); num_nodes]);
+        if falseness_repr == FalsenessRepr::Sparse {
+            for (node_idx, false_nodes) in false_idxs.iter_enumerated() {
+                for false_idx in false_nodes {
+                    falsified_by[*false_idx].push(node_idx.index() as u32);
+                }
+            }
+            // Nodes are visited in ascending order, so every list is already sorted
+        }
+
+        // Now convert nodes from `monument_graph::Node` to `self::Node`, storing their links,
+        // method counts, music and labels separately
+        let num_methods = query.layout.num_methods();
+        let mut links = Vec::new();
+        let mut link_music = Vec::new();
+        let mut succ_offsets = vec![0u32];
+        let mut method_counts = Vec::with_capacity(num_nodes * num_methods);
+        let mut node_music = NodeVec::with_capacity(num_nodes);
+        let mut labels = NodeVec::with_capacity(num_nodes);
         let nodes: NodeVec<_> = (0..num_nodes)
             .map(|index| {
                 // Get the source node and its NodeId
@@ -84,36 +208,61 @@ impl Graph {
                 let (_id, source_node) = index_to_id[index].clone();
 
                 // Generate a BitVec with a 1 for every node which is false against this node
-                let mut falseness = BitVec::from_elem(num_nodes, false);
-                for false_std_id in source_node.false_nodes() {
-                    let false_id = NodeId::Standard(false_std_id.clone());
-                    let false_node_idx = id_to_index[&false_id];
-                    falseness.set(false_node_idx.index(), true);
+                let falseness = match falseness_repr {
+                    FalsenessRepr::Dense => {
+                        let mut falseness = BitVec::from_elem(num_nodes, false);
+                        for false_node_idx in &false_idxs[index] {
+                            falseness.set(false_node_idx.index(), true);
+                        }
+                        falseness
+                    }
+                    FalsenessRepr::Sparse => BitVec::new(),
+                };
+
+                let mut place_bells = BitVec::from_elem(place_bell_indices.len(), false);
+                for place_bell in source_node.place_bells() {
+                    if let Some(idx) = place_bell_indices.get(place_bell) {
+                        place_bells.set(*idx, true);
+                    }
                 }
 
-                let succs = source_node
-                    .successors()
-                    .iter()
-                    .filter_map(|link| {
-                        let link_idx = link.source_idx;
-                        let score =
-                            query.layout.links[link_idx].weight * source_graph.num_parts() as f32;
-                        let succ_idx = id_to_index.get(&link.id)?;
-                        Some(Link::new(score, link_idx, *succ_idx, link.rotation))
-                    })
-                    .collect_vec();
+                for link in source_node.successors() {
+                    if let Some(succ_idx) = id_to_index.get(&link.id) {
+                        links.push(Link::new(
+                            link.score(query, source_graph.num_parts()),
+                            link.source_idx,
+                            *succ_idx,
+                            link.rotation,
+                            link.is_splice(&query.layout),
+                            query.layout.links[link.source_idx].is_call(),
+                        ));
+                        link_music.push(link.music.clone());
+                    }
+                }
+                succ_offsets.push(links.len() as u32);
+                method_counts.extend_from_slice(source_node.method_counts().counts());
+                node_music.push(source_node.music().clone());
+                labels.push(source_node.label().to_owned());
 
                 Node {
                     score: source_node.score(),
                     length: source_node.length() as u32,
-                    method_counts: source_node.method_counts().clone(),
                     dist_to_rounds: source_node.lb_distance_to_rounds as u32,
-                    label: source_node.label().to_owned(),
+                    max_score_per_row: source_node.ub_score_per_row_to_rounds,
+                    est_score_per_row: source_node.est_score_per_row_to_rounds,
                     end: source_node.end(),
                     duffer: source_node.duffer(),
                     dist_to_non_duffer: source_node.lb_distance_to_non_duffer as u32,
-                    succs,
                     falseness,
+                    falsified_by: std::mem::take(&mut falsified_by[index]),
+                    place_bells,
+                    forbidden: source_node.forbidden(),
+                    required_rows: source_node.required_rows(),
+                    course_head_matches: source_node
+                        .course_head_matches()
+                        .iter()
+                        .map(|&n| n as u32)
+                        .collect_vec(),
                 }
             })
             .collect();
@@ -127,13 +276,165 @@ impl Graph {
             }
         }
 
-        Graph { starts, nodes }
+        // 0-length nodes contain no rows, so their score isn't covered by `max_score_per_row`.
+        // They can only occur at the end of a composition, so we bound their score separately.
+        let mut max_end_score = 0f32;
+        for link in &links {
+            let succ_node = &nodes[link.next_node];
+            if succ_node.length == 0 {
+                let score = (link.score.hand + succ_node.score.hand)
+                    .max(link.score.back + succ_node.score.back);
+                max_end_score = max_end_score.max(score.0);
+            }
+        }
+
+        // Convert the fixed prefixes, ignoring any which use nodes removed by optimisation
+        let fixed_prefixes = match &query.comp_prefix {
+            Some(prefix) => source_graph
+                .parse_prefix(prefix, &query.layout)
+                .into_iter()
+                .filter_map(|fixed_prefix| {
+                    let start_node_idx = *id_to_index.get(&fixed_prefix.start_id)?;
+                    let links = fixed_prefix
+                        .links
+                        .iter()
+                        .map(|(link_idx, id)| Some((*link_idx, *id_to_index.get(id)?)))
+                        .collect::<Option<Vec<_>>>()?;
+                    Some((
+                        start_node_idx,
+                        fixed_prefix.start_idx,
+                        fixed_prefix.rotation,
+                        links,
+                    ))
+                })
+                .collect_vec(),
+            None => Vec::new(),
+        };
+
+        Graph {
+            num_parts: source_graph.num_parts() as Rotation,
+            starts,
+            nodes,
+            links,
+            succ_offsets,
+            method_counts,
+            num_methods,
+
+            node_music,
+            link_music,
+            labels,
+
+            max_end_score,
+            fixed_prefixes,
+            falseness_repr,
+            nodes_per_comp,
+        }
     }
 
-    pub fn node_label(&self, idx: NodeIdx) -> String {
-        self.nodes[idx].label.clone()
+    /// Returns `true` if extending a prefix could ever decrease its score (i.e. some node, link or
+    /// new place bell has a negative score)
+    pub fn has_negative_scores(&self, query: &Query) -> bool {
+        let is_negative = |score: &PerStroke<Score>| score.hand.0 < 0.0 || score.back.0 < 0.0;
+        query.atw_weight < 0.0
+            || self.nodes.iter().any(|node| is_negative(&node.score))
+            || self.links.iter().any(|link| is_negative(&link.score))
+    }
+
+    pub fn node_label(&self, idx: NodeIdx) -> &str {
+        &self.labels[idx]
+    }
+
+    /// The successor [`Link`]s of a node
+    pub fn succs(&self, idx: NodeIdx) -> &[Link] {
+        &self.links[self.succ_range(idx)]
+    }
+
+    /// The number of rows of each method in a node
+    pub fn method_counts(&self, idx: NodeIdx) -> &[usize] {
+        let start = idx.index() * self.num_methods;
+        &self.method_counts[start..start + self.num_methods]
+    }
+
+    /// The music generated by a node, if its first row is at the given [`Stroke`]
+    pub fn node_music(&self, idx: NodeIdx, stroke: Stroke) -> &Breakdown {
+        self.node_music[idx].get(stroke)
+    }
+
+    /// The music generated by taking a link (with a given source [`LinkIdx`]) between two nodes
+    pub fn link_music(
+        &self,
+        from: NodeIdx,
+        link_idx: LinkIdx,
+        to: NodeIdx,
+    ) -> &PerStroke<Breakdown> {
+        let range = self.succ_range(from);
+        let offset = self.links[range.clone()]
+            .iter()
+            .position(|l| l.source_idx == link_idx && l.next_node == to)
+            .expect("Link not found");
+        &self.link_music[range.start + offset]
+    }
+
+    fn succ_range(&self, idx: NodeIdx) -> Range<usize> {
+        let start = self.succ_offsets[idx.index()] as usize;
+        let end = self.succ_offsets[idx.index() + 1] as usize;
+        start..end
     }
 }
 
-index_vec::define_index_type! { pub struct NodeIdx = usize; }
+// `u32` indices make links and prefixes smaller
+index_vec::define_index_type! { pub struct NodeIdx = u32; }
 type NodeVec<T> = index_vec::IndexVec<NodeIdx, T>;
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use bellframe::Stage;
+
+    use crate::test_utils::{layout, method, plain_bob, query};
+
+    use super::{Graph, NodeIdx};
+
+    /// The lowered graph should store exactly the same nodes, links and method counts as the
+    /// graph it was built from
+    #[test]
+    fn lowering_preserves_graph() {
+        let methods = [
+            plain_bob(Stage::MINOR),
+            method("Test Bob", Stage::MINOR, "x14x16x16,12"),
+        ];
+        let query = query(layout(&methods, "123456", "123456"), 0..200);
+        let source_graph = query.unoptimised_graph();
+        let graph = Graph::new(&source_graph, &query, None);
+
+        // Nodes are indexed in the order they're iterated over
+        assert_eq!(graph.nodes.len(), source_graph.node_map().len());
+        let indices = source_graph
+            .nodes()
+            .enumerate()
+            .map(|(idx, (id, _node))| (id, NodeIdx::new(idx)))
+            .collect::<HashMap<_, _>>();
+        for (id, source_node) in source_graph.nodes() {
+            let idx = indices[id];
+            assert_eq!(graph.node_label(idx), source_node.label());
+            assert_eq!(graph.nodes[idx].length as usize, source_node.length());
+            assert_eq!(
+                graph.method_counts(idx),
+                source_node.method_counts().counts()
+            );
+            // Links to nodes which aren't in the graph are dropped
+            let expected_succs = source_node
+                .successors()
+                .iter()
+                .filter_map(|link| Some((link.source_idx, *indices.get(&link.id)?)))
+                .collect::<Vec<_>>();
+            let succs = graph
+                .succs(idx)
+                .iter()
+                .map(|link| (link.source_idx, link.next_node))
+                .collect::<Vec<_>>();
+            assert_eq!(succs, expected_succs);
+        }
+    }
+}
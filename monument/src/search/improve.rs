@@ -0,0 +1,173 @@
+//! Local search around an existing composition, trying small edits to its calling.
+
+use itertools::Itertools;
+use log::log;
+
+use crate::{graph::FixedPrefix, music::Score, Comp, Priority, Query, Transposition};
+
+use super::{prefix::CompPrefix, SearchData};
+
+/// The maximum number of links which can be changed to generate a neighbouring composition.  Two
+/// changes are enough to e.g. move a single to a different lead.
+const MAX_CHANGES: usize = 2;
+
+/// Each link in a composition, represented by the display name of the link and the label of the
+/// node it leads to.  Neighbouring compositions are generated by following the same sequence of
+/// tokens but with a few of them changed.
+type Token = (String, String);
+
+/// Repeatedly replace `comp` with the best composition which can be made by changing up to
+/// [`MAX_CHANGES`] of its links, until no further improvement can be made.  Every composition
+/// found which is better than `comp` is returned, in ascending order of goodness.
+pub(crate) fn improve(graph: &crate::graph::Graph, query: &Query, comp: &FixedPrefix) -> Vec<Comp> {
+    // The priority and transposition are irrelevant, because prefixes aren't stored in a frontier
+    let lowered_graph = super::Graph::new(graph, query, None);
+    let data = SearchData::new(
+        &lowered_graph,
+        query,
+        Priority::AvgScore,
+        Transposition::Off,
+    );
+
+    // Edits never change the start of the composition
+    let start = data
+        .graph
+        .starts
+        .iter()
+        .find(|(_, idx, rot)| *idx == comp.start_idx && *rot == comp.rotation)
+        .and_then(|&(node_idx, start_idx, rot)| CompPrefix::start(node_idx, start_idx, rot, &data));
+    let start = match start {
+        Some(s) => s,
+        None => return Vec::new(), // The start has been removed by optimisation
+    };
+
+    let mut tokens = comp
+        .links
+        .iter()
+        .map(|(link_idx, id)| {
+            let label = graph.get_node(id).map_or("", |node| node.label());
+            (
+                query.layout.links[*link_idx].display_name.clone(),
+                label.to_owned(),
+            )
+        })
+        .collect_vec();
+    // If the original composition isn't valid, then any valid neighbour is an improvement
+    let mut original_comps = Vec::new();
+    explore(&start, &tokens, 0, false, &data, &mut original_comps);
+    let mut best_score = original_comps
+        .iter()
+        .map(|c| c.avg_score)
+        .max()
+        .unwrap_or_else(|| Score::from(f32::NEG_INFINITY));
+
+    // Hill-climb until no neighbour is better than the current best composition
+    let mut improved_comps = Vec::<Comp>::new();
+    loop {
+        let mut neighbours = Vec::new();
+        explore(&start, &tokens, MAX_CHANGES, false, &data, &mut neighbours);
+        neighbours.retain(|c| c.avg_score > best_score);
+
+        let best_neighbour = match neighbours.iter().max_by_key(|c| c.avg_score) {
+            Some(c) => c,
+            None => break, // No more improvements can be made
+        };
+        log::debug!(
+            "Improved comp to {}",
+            best_neighbour.display_string(&query.layout)
+        );
+        best_score = best_neighbour.avg_score;
+        tokens = comp_tokens(best_neighbour, query);
+        improved_comps.extend(neighbours);
+    }
+
+    // Return the best unique comps, worst first
+    let mut comps = improved_comps
+        .into_iter()
+        .unique_by(|c| c.display_string(&query.layout))
+        .collect_vec();
+    comps.sort_by_key(|c| c.avg_score);
+    let num_to_remove = comps.len().saturating_sub(query.num_comps);
+    comps.drain(..num_to_remove);
+    comps
+}
+
+/// Extend `prefix` by following `tokens`, except that up to `changes_left` of the tokens can be
+/// replaced by any other link.  Every valid composition which is different to the one being
+/// followed (or every valid composition, if `changes_left` is 0) is added to `out`.
+fn explore(
+    prefix: &CompPrefix,
+    tokens: &[Token],
+    changes_left: usize,
+    is_changed: bool,
+    data: &SearchData,
+    out: &mut Vec<Comp>,
+) {
+    let (next_token, rest) = match tokens.split_first() {
+        Some(t) => t,
+        None => {
+            if is_changed || changes_left == 0 {
+                out.extend(prefix.to_comp(data));
+            }
+            return;
+        }
+    };
+
+    for (link, succ_prefix) in prefix.successors_with_links(data) {
+        let display_name = &data.query.layout.links[link.source_idx].display_name;
+        let label = data.graph.node_label(link.next_node);
+        if display_name == &next_token.0 && label == next_token.1 {
+            explore(&succ_prefix, rest, changes_left, is_changed, data, out);
+        } else if changes_left > 0 {
+            explore(&succ_prefix, rest, changes_left - 1, true, data, out);
+        }
+    }
+}
+
+/// Convert a [`Comp`] into the [`Token`]s of its links
+fn comp_tokens(comp: &Comp, query: &Query) -> Vec<Token> {
+    comp.links
+        .iter()
+        .map(|(link_idx, label)| {
+            let display_name = &query.layout.links[*link_idx].display_name;
+            (display_name.clone(), label.clone())
+        })
+        .collect_vec()
+}
+
+#[cfg(test)]
+mod tests {
+    use bellframe::Stage;
+
+    use crate::{
+        test_utils::{config, layout, plain_bob, query, run, runs},
+        Config, Strategy,
+    };
+
+    #[test]
+    fn improve_comp() {
+        let mut query = query(
+            layout(&[plain_bob(Stage::MINOR)], "123456", "123456"),
+            0..200,
+        );
+        query.num_comps = 20;
+        query.music_types = vec![runs(Stage::MINOR, 4)];
+        let depth_first = Config {
+            strategy: Strategy::DepthFirst,
+            ..config()
+        };
+        let comps = run(query.clone(), depth_first);
+        let improve = |comp_idx: usize| {
+            let call_string = comps[comp_idx].display_string(&query.layout);
+            crate::improve_comp(&query, &mut config(), &call_string).unwrap()
+        };
+
+        // Every improvement is better than the original composition
+        let worst_score = comps[0].avg_score;
+        assert!(improve(0).iter().all(|c| c.avg_score > worst_score));
+        // Depth-first search is exact, so its best composition can't be improved
+        assert!(improve(comps.len() - 1).is_empty());
+        // Call strings which can't be rung aren't improved
+        assert!(crate::improve_comp(&query, &mut config(), "not a composition").is_none());
+    }
+}
@@ -1,318 +1,101 @@
-use std::{cmp::Ordering, collections::BinaryHeap, fmt::Debug, rc::Rc};
+use std::ops::{Range, RangeInclusive};
 
-use bit_vec::BitVec;
-use log::log;
+use itertools::Itertools;
 
-use crate::{
-    layout::{LinkIdx, Rotation, StartIdx},
-    music::Score,
-    utils::{coprime_bitmap, RowCounts},
-    Comp, Query,
-};
+use crate::{layout::Rotation, utils::coprime_bitmap, Comp, Priority, Query, Transposition};
 
+mod falseness;
 mod graph;
-
+mod improve;
+mod pool;
+mod prefix;
+mod spill;
+pub(crate) mod strategy;
+mod transposition;
+
+pub use falseness::FalsenessRepr;
 pub use graph::Graph;
-use graph::NodeIdx;
-
-/// Searches a [`Graph`](m_gr::Graph) for composition, according to some extra [`Data`].  For each
-/// composition `c` found, `on_comp(c)` will be called.
-pub(crate) fn search<CompFn: FnMut(Comp)>(
-    graph: &crate::graph::Graph,
+pub(crate) use improve::improve;
+pub(crate) use pool::WorkPool;
+pub(crate) use spill::prepare_spill_dir;
+use strategy::SearchStrategy;
+
+/// Searches a lowered [`Graph`] for compositions, according to some extra [`SearchData`].  For
+/// each composition `c` found, `on_comp(c)` will be called, returning `false` if `c` was rejected
+/// (e.g. for being too similar to a composition which has already been found).
+///
+/// The same [`Graph`] must be shared between every thread, so that prefixes shared between the
+/// threads refer to the same nodes.
+pub(crate) fn search<CompFn: FnMut(Comp) -> bool>(
+    graph: &Graph,
     query: &Query,
-    queue_limit: usize,
+    strategy: &mut dyn SearchStrategy,
+    priority: Priority,
+    transposition: Transposition,
     mut comp_fn: CompFn,
 ) {
-    let max_duffer_rows = query.max_duffer_rows.map_or(u32::MAX, |m| m as u32);
-    let len_range = (query.len_range.start as u32)..(query.len_range.end as u32);
-    let num_parts = graph.num_parts() as Rotation;
-    let rotation_bitmap = coprime_bitmap(num_parts);
-    // Lower the hash-based graph into a graph that's immutable but faster to traverse
-    let graph = self::graph::Graph::new(graph, query);
-
-    // Initialise the frontier to just the start nodes
-    let mut frontier = BinaryHeap::new();
-    for (node_idx, start_idx, rotation) in graph.starts.iter() {
-        let node = &graph.nodes[*node_idx];
-        frontier.push(CompPrefix::new(
-            CompPath::Start(*start_idx),
-            *node_idx,
-            node.falseness.clone(),
-            *rotation,
-            node.score,
-            node.length,
-            node.method_counts.clone(),
-            if node.duffer {
-                node.length // Rounds counts as a non-duffer
-            } else {
-                0
-            },
-        ));
-    }
-
-    // Repeatedly choose the best prefix and expand it (i.e. add each way of extending it to the
-    // frontier).
-    let mut iter_count = 0;
-    let mut num_comps = 0;
-    while let Some(prefix) = frontier.pop() {
-        let CompPrefix {
-            inner,
-            avg_score,
-            length,
-        } = prefix;
-        let PrefixInner {
-            path,
-            node_idx,
-            unreachable_nodes,
-            rotation,
-            len_since_non_duffer,
-
-            score,
-            method_counts,
-        } = *inner;
-        let node = &graph.nodes[node_idx];
-
-        // Check if the comp has come round
-        if let Some(end) = node.end {
-            if len_range.contains(&length)
-                && method_counts.is_feasible(0, query.method_count_range.clone())
-                && rotation_bitmap & (1 << rotation) != 0
-            {
-                let (start_idx, start_node_label, links) = path.flatten(&graph, query);
-                let comp = Comp {
-                    start_idx,
-                    start_node_label,
-                    links,
-                    end,
-
-                    rotation,
-                    length: length as usize,
-                    method_counts,
-                    score,
-                    avg_score,
-                };
-                comp_fn(comp);
-                num_comps += 1;
-
-                if num_comps == query.num_comps {
-                    break; // Stop the search once we've got enough comps
-                }
-            }
-            continue; // Don't expand comps after they've come round
-        }
-
-        // Expand this node
-        let path = Rc::new(path);
-        for link in &node.succs {
-            let next_idx = link.next_node;
-            let succ_node = &graph.nodes[next_idx];
-
-            let rotation = (rotation + link.rot) % num_parts;
-            let length = length + succ_node.length;
-            let score = score + succ_node.score + link.score;
-            let method_counts = &method_counts + &succ_node.method_counts;
-            let len_since_non_duffer = if succ_node.duffer {
-                len_since_non_duffer + succ_node.length
-            } else {
-                0 // Reset the counter whenever we encounter a non-duffer node
-            };
-
-            if length + succ_node.dist_to_rounds >= len_range.end {
-                continue; // Node would make comp too long
-            }
-            if len_since_non_duffer + succ_node.dist_to_non_duffer >= max_duffer_rows {
-                continue; // Can't get to a non-duffer fast enough
-            }
-            if unreachable_nodes.get(next_idx.index()).unwrap() {
-                continue; // Node is false against something already in the comp
-            }
-            if !method_counts.is_feasible(
-                (len_range.end - length) as usize,
-                query.method_count_range.clone(),
-            ) {
-                continue; // Can't recover the method balance before running out of rows
-            }
-
-            // Compute which nodes are unreachable after this node has been added
-            let mut new_unreachable_nodes = unreachable_nodes.clone();
-            new_unreachable_nodes.or(&succ_node.falseness);
-
-            frontier.push(CompPrefix::new(
-                CompPath::Cons(path.clone(), link.source_idx, next_idx),
-                next_idx,
-                new_unreachable_nodes,
-                rotation,
-                score,
-                length,
-                method_counts,
-                len_since_non_duffer,
-            ));
-        }
-
-        // If the queue gets too long, then halve its size
-        if frontier.len() >= queue_limit {
-            log::debug!("Truncating queue");
-            truncate_heap(&mut frontier, queue_limit / 2);
-        }
-
-        // Print stats every so often
-        iter_count += 1;
-        if iter_count % 1_000_000 == 0 {
-            let mut total_len = 0;
-            let mut max_len = 0;
-            frontier.iter().for_each(|n| {
-                total_len += n.length as usize;
-                max_len = max_len.max(n.length);
-            });
-
-            log::info!(
-                "{} iters, {} items in queue, avg/max len {:.0}/{}",
-                iter_count,
-                frontier.len(),
-                total_len as f32 / frontier.len() as f32,
-                max_len
-            );
-        }
-    }
-}
-
-fn truncate_heap<T: Ord>(heap_ref: &mut BinaryHeap<T>, len: usize) {
-    let heap = std::mem::take(heap_ref);
-    let mut nodes = heap.into_vec();
-    nodes.sort_by(|a, b| b.cmp(a)); // Sort highest score first
-    if len < nodes.len() {
-        nodes.drain(len..);
-    }
-    *heap_ref = BinaryHeap::from(nodes);
+    let data = SearchData::new(graph, query, priority, transposition);
+    strategy.search(&data, &mut comp_fn);
 }
 
-///////////////////
-// COMP PREFIXES //
-///////////////////
-
-#[derive(Debug, Clone)]
-struct CompPrefix {
-    /// Data for this prefix which isn't accessed as much as `avg_score` or `length`.  We store it
-    /// in a [`Box`] because the frontier spends a lot of time swapping elements, and copying a
-    /// 128-bit struct is much much faster than copying an inlined [`PrefixInner`].  `avg_score`
-    /// and `length` are accessed so often that they are left unboxed.
-    inner: Box<PrefixInner>,
-    /// Score per row in the composition
-    avg_score: Score,
-    /// Length refers to the **end** of the current node.  We use `u32` because [`Score`] is also
-    /// 32 bits long, making `CompPrefix` pack into 128 bits
-    length: u32,
-}
-
-/// The prefix of a composition.  These are ordered by score.
-#[derive(Debug, Clone)]
-struct PrefixInner {
-    /// The path traced to this node
-    path: CompPath,
-
-    node_idx: NodeIdx,
-    unreachable_nodes: BitVec,
-
-    len_since_non_duffer: u32,
-
-    /// The number of part heads through which we have rotated.  This is kept in the range
-    /// `0..graph.num_parts`
-    rotation: Rotation,
-
-    /// Score refers to the **end** of the current node
-    score: Score,
-    /// Method counts refers to the **end** of the current node
-    method_counts: RowCounts,
+/// Immutable data which is shared between every [`CompPrefix`] in a search
+#[derive(Debug)]
+pub(crate) struct SearchData<'q> {
+    /// The lowered version of the [`Graph`](crate::graph::Graph) being searched
+    graph: &'q Graph,
+    query: &'q Query,
+
+    num_parts: Rotation,
+    /// Bitmap of which rotations are coprime with `num_parts` (i.e. which rotations will generate
+    /// every part)
+    rotation_bitmap: u64,
+    len_range: Range<u32>,
+    max_duffer_rows: u32,
+    min_com: u32,
+    max_com: u32,
+    course_count_ranges: Vec<RangeInclusive<u32>>,
+    /// Bitmask containing a `1` for every required row
+    all_required_rows: u64,
+    /// How prefixes are ordered in the frontier
+    priority: Priority,
+    /// How prefixes which reach the same search state are merged
+    transposition: Transposition,
 }
 
-impl CompPrefix {
+impl<'q> SearchData<'q> {
     fn new(
-        path: CompPath,
-        node_idx: NodeIdx,
-        unreachable_nodes: BitVec,
-        rotation: Rotation,
-        score: Score,
-        length: u32,
-        method_counts: RowCounts,
-        len_since_non_duffer: u32,
+        graph: &'q Graph,
+        query: &'q Query,
+        priority: Priority,
+        transposition: Transposition,
     ) -> Self {
+        let num_parts = graph.num_parts;
+        let course_count_ranges = query
+            .course_head_counts
+            .iter()
+            .map(|(_masks, range)| {
+                let min = range.min.map_or(0, |m| m as u32);
+                let max = range.max.map_or(u32::MAX, |m| m as u32);
+                min..=max
+            })
+            .collect_vec();
         Self {
-            inner: Box::new(PrefixInner {
-                path,
-                node_idx,
-                unreachable_nodes,
-                rotation,
-                score,
-                method_counts,
-                len_since_non_duffer,
-            }),
-            length,
-            avg_score: score / length as f32,
-        }
-    }
-}
-
-impl PartialEq for CompPrefix {
-    fn eq(&self, other: &Self) -> bool {
-        self.avg_score == other.avg_score
-    }
-}
-
-impl Eq for CompPrefix {}
-
-impl PartialOrd for CompPrefix {
-    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        Some(self.cmp(other))
-    }
-}
-
-impl Ord for CompPrefix {
-    fn cmp(&self, other: &Self) -> Ordering {
-        self.avg_score.cmp(&other.avg_score)
-    }
-}
-
-/// A route through the composition graph, stored as a reverse linked list.  This allows for
-/// multiple compositions with the same prefix to share the data for that prefix.
-#[derive(Debug, Clone)]
-enum CompPath {
-    /// The start of a composition, along with the index within `Graph::start_nodes` of this
-    /// specific start
-    Start(StartIdx),
-    /// The composition follows the sequence in the [`Rc`], followed by taking the `n`th successor
-    /// to that node.
-    Cons(Rc<Self>, LinkIdx, NodeIdx),
-}
-
-impl CompPath {
-    fn flatten(&self, graph: &Graph, query: &Query) -> (StartIdx, String, Vec<(LinkIdx, String)>) {
-        let mut links = Vec::new();
-        let (start_idx, start_node_label) = self.flatten_recursive(graph, query, &mut links);
-        (start_idx, start_node_label, links)
-    }
-
-    /// Recursively flatten `self`, returning the start idx
-    fn flatten_recursive(
-        &self,
-        graph: &Graph,
-        query: &Query,
-        out: &mut Vec<(LinkIdx, String)>,
-    ) -> (StartIdx, String) {
-        match self {
-            Self::Start(start_idx) => {
-                let (start_node_idx, _, _) = graph
-                    .starts
-                    .iter()
-                    .find(|(_, start_idx_2, _)| start_idx == start_idx_2)
-                    .unwrap();
-                let label = graph.node_label(*start_node_idx);
-                (*start_idx, label)
-            }
-            Self::Cons(lhs, link, node_idx) => {
-                let start = lhs.flatten_recursive(graph, query, out);
-                out.push((*link, graph.node_label(*node_idx)));
-                start
-            }
+            graph,
+            query,
+
+            num_parts,
+            rotation_bitmap: coprime_bitmap(num_parts),
+            len_range: (query.len_range.start as u32)..(query.len_range.end as u32),
+            max_duffer_rows: query.max_duffer_rows.map_or(u32::MAX, |m| m as u32),
+            min_com: query.changes_of_method.min.map_or(0, |m| m as u32),
+            max_com: query.changes_of_method.max.map_or(u32::MAX, |m| m as u32),
+            course_count_ranges,
+            all_required_rows: match query.required_rows.len() {
+                64 => u64::MAX,
+                n => (1u64 << n) - 1,
+            },
+            priority,
+            transposition,
         }
     }
 }
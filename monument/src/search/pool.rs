@@ -0,0 +1,204 @@
+//! A pool of composition prefixes which is shared between all the search threads, so that
+//! threads which run out of work can take prefixes from threads which still have plenty.
+
+use std::{
+    cmp::Reverse,
+    sync::{
+        atomic::{AtomicBool, AtomicU32, AtomicUsize, Ordering},
+        Condvar, Mutex,
+    },
+};
+
+use crate::{music::Score, Comp, Query};
+
+use super::prefix::CompPrefix;
+
+/// Prefixes which have been given away by busy threads, waiting to be taken by idle threads.
+/// This also keeps track of when the search has finished, either because every thread has run out
+/// of work or because enough compositions have been found.
+#[derive(Debug)]
+pub(crate) struct WorkPool {
+    num_threads: usize,
+    state: Mutex<PoolState>,
+    /// Notified whenever prefixes are added to the pool, or the search finishes
+    condvar: Condvar,
+    /// The number of threads which are waiting for work.  This is also stored in `state`, but is
+    /// duplicated here so that busy threads can check it without locking the [`Mutex`].
+    num_waiting: AtomicUsize,
+    is_finished: AtomicBool,
+    /// The number of compositions found by all the threads
+    num_comps: AtomicUsize,
+
+    /// For exhaustive searches, the best compositions found by any thread
+    best_comps: Mutex<BestComps>,
+    /// The bits of `best_comps.score_to_beat()`, duplicated so that threads can prune prefixes
+    /// without locking the [`Mutex`]
+    score_to_beat: AtomicU32,
+    /// The number of threads which have finished an exhaustive search
+    num_finished_threads: AtomicUsize,
+}
+
+#[derive(Debug, Default)]
+struct PoolState {
+    prefixes: Vec<CompPrefix>,
+    num_waiting: usize,
+}
+
+impl WorkPool {
+    pub(crate) fn new(num_threads: usize, query: &Query) -> Self {
+        let best_comps = BestComps::new(query);
+        Self {
+            num_threads,
+            state: Mutex::new(PoolState::default()),
+            condvar: Condvar::new(),
+            num_waiting: AtomicUsize::new(0),
+            is_finished: AtomicBool::new(false),
+            num_comps: AtomicUsize::new(0),
+
+            score_to_beat: AtomicU32::new(best_comps.score_to_beat().0.to_bits()),
+            best_comps: Mutex::new(best_comps),
+            num_finished_threads: AtomicUsize::new(0),
+        }
+    }
+
+    pub(crate) fn num_threads(&self) -> usize {
+        self.num_threads
+    }
+
+    /// Returns `true` if another thread is waiting for prefixes to be shared with it
+    pub(super) fn wants_work(&self) -> bool {
+        self.num_waiting.load(Ordering::Relaxed) > 0
+    }
+
+    /// Add some prefixes to the pool, so that idle threads can expand them
+    pub(super) fn share(&self, prefixes: Vec<CompPrefix>) {
+        let mut state = self.state.lock().unwrap();
+        state.prefixes.extend(prefixes);
+        self.condvar.notify_all();
+    }
+
+    /// Called by threads which have run out of prefixes.  This blocks until either some prefixes
+    /// are shared (in which case they are returned) or the search has finished (in which case
+    /// `None` is returned).  The search finishes once every thread is waiting for work.
+    pub(super) fn take(&self) -> Option<Vec<CompPrefix>> {
+        let mut state = self.state.lock().unwrap();
+        state.num_waiting += 1;
+        self.num_waiting.store(state.num_waiting, Ordering::Relaxed);
+        loop {
+            if !state.prefixes.is_empty() {
+                state.num_waiting -= 1;
+                self.num_waiting.store(state.num_waiting, Ordering::Relaxed);
+                return Some(std::mem::take(&mut state.prefixes));
+            }
+            if self.is_finished() {
+                return None;
+            }
+            if state.num_waiting == self.num_threads {
+                // Every thread is out of work, so the search is over.  We already hold the lock, so
+                // can't call `self.finish()`
+                self.is_finished.store(true, Ordering::Relaxed);
+                self.condvar.notify_all();
+                return None;
+            }
+            state = self.condvar.wait(state).unwrap();
+        }
+    }
+
+    /// Record that a composition has been found, returning the total number of compositions found
+    /// by all the threads
+    pub(super) fn add_comp(&self) -> usize {
+        self.num_comps.fetch_add(1, Ordering::Relaxed) + 1
+    }
+
+    /// Stop the search, waking any threads which are waiting for work
+    pub(super) fn finish(&self) {
+        // Hold the lock so that waiting threads can't miss the notification
+        let _state = self.state.lock().unwrap();
+        self.is_finished.store(true, Ordering::Relaxed);
+        self.condvar.notify_all();
+    }
+
+    pub(super) fn is_finished(&self) -> bool {
+        self.is_finished.load(Ordering::Relaxed)
+    }
+
+    /* EXHAUSTIVE SEARCHES */
+
+    /// Add a composition to the best compositions found by all the threads
+    pub(super) fn add_best_comp(&self, comp: Comp) {
+        let mut best_comps = self.best_comps.lock().unwrap();
+        best_comps.add(comp);
+        let score_to_beat = best_comps.score_to_beat();
+        self.score_to_beat
+            .store(score_to_beat.0.to_bits(), Ordering::Relaxed);
+    }
+
+    /// The average score which a new composition has to beat to be one of the best compositions
+    /// found by any thread
+    pub(super) fn score_to_beat(&self) -> Score {
+        Score::from(f32::from_bits(self.score_to_beat.load(Ordering::Relaxed)))
+    }
+
+    /// Record that this thread has finished its exhaustive search.  Once every thread has
+    /// finished, the last one gets the best compositions found by all the threads (so that they're
+    /// only passed to `comp_fn` once).  All the other threads get `None`.
+    pub(super) fn finish_exhaustive(&self) -> Option<Vec<Comp>> {
+        let num_finished = self.num_finished_threads.fetch_add(1, Ordering::AcqRel) + 1;
+        if num_finished < self.num_threads {
+            return None;
+        }
+        Some(std::mem::take(&mut self.best_comps.lock().unwrap().comps))
+    }
+}
+
+/// The best compositions found so far, which exhaustive strategies can use to prune prefixes which
+/// can't beat any of them
+#[derive(Debug, Clone)]
+struct BestComps {
+    /// The comps, sorted best first
+    comps: Vec<Comp>,
+    limit: usize,
+    /// See [`Query::max_overlap`](crate::Query::max_overlap)
+    max_overlap: Option<f32>,
+}
+
+impl BestComps {
+    fn new(query: &Query) -> Self {
+        Self {
+            comps: Vec::new(),
+            limit: query.num_comps,
+            max_overlap: query.max_overlap,
+        }
+    }
+
+    /// Add a new [`Comp`], removing the worst comp if there are too many
+    fn add(&mut self, comp: Comp) {
+        if let Some(max_overlap) = self.max_overlap {
+            let is_similar = |c: &Comp| c.overlap(&comp) > max_overlap;
+            // Don't add `comp` if it's too similar to a better comp
+            if self
+                .comps
+                .iter()
+                .any(|c| is_similar(c) && c.avg_score >= comp.avg_score)
+            {
+                return;
+            }
+            // Otherwise, `comp` replaces any worse comps which are too similar to it.  This can
+            // lower `score_to_beat` after prefixes have already been pruned, so diverse searches
+            // aren't guaranteed to be exact.
+            self.comps.retain(|c| !is_similar(c));
+        }
+        self.comps.push(comp);
+        self.comps.sort_by_key(|c| Reverse(c.avg_score));
+        self.comps.truncate(self.limit);
+    }
+
+    /// The average score which a new composition has to beat to be added to `self`
+    fn score_to_beat(&self) -> Score {
+        if self.comps.len() < self.limit {
+            Score::from(f32::NEG_INFINITY)
+        } else {
+            self.comps.last().unwrap().avg_score
+        }
+    }
+}
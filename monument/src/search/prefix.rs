@@ -0,0 +1,805 @@
+//! Composition prefixes, and the rules for starting, extending and completing them.
+
+use std::{
+    cmp::Ordering,
+    fmt::Debug,
+    io::{self, Read, Write},
+    mem::size_of,
+    sync::Arc,
+};
+
+use bit_vec::BitVec;
+use itertools::Itertools;
+
+use crate::{
+    layout::{LinkIdx, Rotation, StartIdx},
+    music::{Breakdown, Score, Stroke},
+    utils::RowCounts,
+    Comp, Priority, Query, Transposition,
+};
+
+use super::{
+    falseness::Unreachable,
+    graph::{Graph, Link, NodeIdx},
+    spill::{read_bit_vec, read_u32, read_u64, write_bit_vec, write_u32, write_u64},
+    transposition::StateKey,
+    SearchData,
+};
+
+#[derive(Debug, Clone)]
+pub(super) struct CompPrefix {
+    /// Data for this prefix which isn't accessed as much as `priority` or `length`.  We store it
+    /// in a [`Box`] because the frontier spends a lot of time swapping elements, and copying a
+    /// 128-bit struct is much much faster than copying an inlined [`PrefixInner`].  `priority`
+    /// and `length` are accessed so often that they are left unboxed.
+    inner: Box<PrefixInner>,
+    /// The value used to order prefixes in the frontier, as determined by the [`Priority`]
+    priority: Score,
+    /// Length refers to the **end** of the current node.  We use `u32` because [`Score`] is also
+    /// 32 bits long, making `CompPrefix` pack into 128 bits
+    pub(super) length: u32,
+}
+
+/// The prefix of a composition.  These are ordered by their [`Priority`].
+#[derive(Debug, Clone)]
+struct PrefixInner {
+    /// The path traced to this node
+    path: CompPath,
+
+    node_idx: NodeIdx,
+    unreachable_nodes: Unreachable,
+    /// The place bells which have been rung so far (empty if we don't care about ATW)
+    place_bells: BitVec,
+
+    len_since_non_duffer: u32,
+    /// The number of changes of method made so far, counting every part
+    changes_of_method: u32,
+    /// Bitmask of which of the [`Query::required_rows`] have been rung so far
+    required_rows: u64,
+    /// For each of the [`Query::course_head_counts`], how many matching courses have been rung so
+    /// far
+    course_counts: Vec<u32>,
+
+    /// The number of part heads through which we have rotated.  This is kept in the range
+    /// `0..graph.num_parts`
+    rotation: Rotation,
+
+    /// Score refers to the **end** of the current node
+    score: Score,
+    /// Method counts refers to the **end** of the current node
+    method_counts: RowCounts,
+}
+
+impl CompPrefix {
+    fn new(inner: PrefixInner, length: u32, data: &SearchData) -> Self {
+        let avg_score = inner.score / length as f32;
+        let mut prefix = Self {
+            priority: avg_score,
+            inner: Box::new(inner),
+            length,
+        };
+        prefix.priority = match data.priority {
+            Priority::AvgScore => avg_score,
+            Priority::Estimated => {
+                let node = &data.graph.nodes[prefix.inner.node_idx];
+                match node.est_score_per_row {
+                    // Assume that the shortest route to rounds will be taken
+                    Some(est_score_per_row) => {
+                        let rows_left = node.dist_to_rounds as f32;
+                        let est_score = prefix.inner.score + est_score_per_row * rows_left;
+                        est_score / (length as f32 + rows_left)
+                    }
+                    None => avg_score,
+                }
+            }
+            Priority::UpperBound => prefix.avg_score_bound(data),
+        };
+        prefix
+    }
+
+    /// Create the prefixes from which the search should start.  Usually these are just the start
+    /// nodes, but if the [`Query`] specifies a `comp_prefix` then the search starts from every
+    /// way of ringing that prefix.
+    pub(super) fn starts(data: &SearchData) -> Vec<Self> {
+        if data.query.comp_prefix.is_none() {
+            return data
+                .graph
+                .starts
+                .iter()
+                .filter_map(|&(node_idx, start_idx, rotation)| {
+                    Self::start(node_idx, start_idx, rotation, data)
+                })
+                .collect_vec();
+        }
+
+        // Build each fixed prefix one link at a time, dropping any prefixes which can't be
+        // extended into a valid composition
+        let mut prefixes = Vec::new();
+        'prefix_loop: for (node_idx, start_idx, rotation, links) in &data.graph.fixed_prefixes {
+            let mut prefix = match Self::start(*node_idx, *start_idx, *rotation, data) {
+                Some(p) => p,
+                None => continue,
+            };
+            for (link_idx, next_idx) in links {
+                let link = data
+                    .graph
+                    .succs(prefix.inner.node_idx)
+                    .iter()
+                    .find(|l| l.source_idx == *link_idx && l.next_node == *next_idx);
+                let path = Arc::new(prefix.inner.path.clone());
+                prefix = match link.and_then(|link| prefix.extend(link, &path, data)) {
+                    Some(p) => p,
+                    None => continue 'prefix_loop,
+                };
+            }
+            prefixes.push(prefix);
+        }
+        prefixes
+    }
+
+    /// Create a prefix containing just a start node, or `None` if that start node can't be used
+    pub(super) fn start(
+        node_idx: NodeIdx,
+        start_idx: StartIdx,
+        rotation: Rotation,
+        data: &SearchData,
+    ) -> Option<Self> {
+        let node = &data.graph.nodes[node_idx];
+        if node.forbidden.back {
+            return None; // The first row of the composition is always at backstroke
+        }
+        let atw_score = data.query.atw_weight * count_ones(&node.place_bells) as f32;
+        Some(Self::new(
+            PrefixInner {
+                path: CompPath::Start(start_idx),
+                node_idx,
+                unreachable_nodes: Unreachable::new(node_idx, data.graph),
+                place_bells: node.place_bells.clone(),
+                rotation,
+                len_since_non_duffer: if node.duffer {
+                    node.length // Rounds counts as a non-duffer
+                } else {
+                    0
+                },
+                changes_of_method: 0,
+                required_rows: node.required_rows.back,
+                course_counts: node.course_head_matches.clone(),
+                // The first row of the composition is always at backstroke
+                score: node.score.back + atw_score,
+                method_counts: RowCounts::from_counts(data.graph.method_counts(node_idx).to_vec()),
+            },
+            node.length,
+            data,
+        ))
+    }
+
+    /// Returns `true` if this prefix has come round (and therefore can't be extended any further)
+    pub(super) fn is_complete(&self, data: &SearchData) -> bool {
+        data.graph.nodes[self.inner.node_idx].end.is_some()
+    }
+
+    /// If this prefix has come round and satisfies all the [`Query`]'s requirements, convert it
+    /// into a [`Comp`]
+    pub(super) fn to_comp(&self, data: &SearchData) -> Option<Comp> {
+        let PrefixInner {
+            path,
+            node_idx,
+            place_bells,
+            rotation,
+            changes_of_method,
+            required_rows,
+            course_counts,
+            score,
+            method_counts,
+            ..
+        } = &*self.inner;
+        let end = data.graph.nodes[*node_idx].end?;
+
+        let is_valid = data.len_range.contains(&self.length)
+            && method_counts.is_feasible(0, data.query.method_count_range.clone())
+            && *changes_of_method >= data.min_com
+            && (!data.query.atw || place_bells.all())
+            && *required_rows == data.all_required_rows
+            && course_counts
+                .iter()
+                .zip_eq(&data.course_count_ranges)
+                .all(|(count, range)| range.contains(count))
+            && data.rotation_bitmap & (1 << rotation) != 0
+            // Scores assume that every part starts at the same stroke
+            && (data.num_parts == 1 || (self.length / data.num_parts as u32) % 2 == 0);
+        if !is_valid {
+            return None;
+        }
+
+        let (start_idx, start_node_label, links, music) =
+            path.flatten(data.graph, data.query, data.num_parts);
+        Some(Comp {
+            start_idx,
+            start_node_label,
+            links,
+            end,
+
+            rotation: *rotation,
+            length: self.length as usize,
+            method_counts: method_counts.clone(),
+            changes_of_method: *changes_of_method as usize,
+            score: *score,
+            avg_score: *score / self.length as f32,
+            music,
+        })
+    }
+
+    /// An upper bound on the average score of any composition which starts with this prefix
+    pub(super) fn avg_score_bound(&self, data: &SearchData) -> Score {
+        let node = &data.graph.nodes[self.inner.node_idx];
+        // Score which can be generated after this prefix without adding any more rows
+        let mut extra_score = data.graph.max_end_score;
+        if data.query.atw_weight > 0.0 {
+            let place_bells = &self.inner.place_bells;
+            let place_bells_left = place_bells.len() - count_ones(place_bells);
+            extra_score += data.query.atw_weight * place_bells_left as f32;
+        }
+        let score = self.inner.score.0 + extra_score;
+        let length = self.length as f32;
+
+        // As more rows are added, the average score moves monotonically towards
+        // `max_score_per_row`.  So the bound is maximised at one end of the range of possible
+        // lengths.
+        let avg_score_after = |rows_left: u32| -> f32 {
+            if rows_left == 0 {
+                score / length // Avoid `0 * inf`
+            } else {
+                let rows_left = rows_left as f32;
+                (score + node.max_score_per_row * rows_left) / (length + rows_left)
+            }
+        };
+        let min_rows_left = node.dist_to_rounds;
+        let max_rows_left = data.len_range.end.saturating_sub(self.length + 1);
+        let bound = avg_score_after(min_rows_left).max(avg_score_after(max_rows_left));
+        Score::from(bound)
+    }
+
+    /// An estimate of the number of bytes of memory used by each prefix in the frontier, including
+    /// the heap allocations owned by that prefix and the transposition table entry which it may
+    /// add.  This assumes that each prefix allocates one new node of its path (the rest of the path
+    /// is shared with other prefixes).
+    pub(super) fn size_estimate(data: &SearchData) -> usize {
+        /// Rough number of bytes used by the allocator to track each allocation
+        const ALLOC_OVERHEAD: usize = 16;
+        /// Size of a heap allocation of `bytes` bytes
+        fn alloc_size(bytes: usize) -> usize {
+            bytes + ALLOC_OVERHEAD
+        }
+        /// Size of the heap allocation for a `BitVec` of `num_bits` bits (stored as `u32` blocks)
+        fn bit_vec_size(num_bits: usize) -> usize {
+            alloc_size((num_bits + 31) / 32 * size_of::<u32>())
+        }
+
+        let some_node = data.graph.nodes.iter().next();
+        let num_place_bells = some_node.map_or(0, |n| n.place_bells.len());
+        let num_method_counts = data.query.layout.num_methods();
+        // Heap allocations which are also cloned into the prefix's `StateKey`
+        let unreachable_size = alloc_size(Unreachable::heap_size_estimate(data.graph));
+        let place_bells_size = bit_vec_size(num_place_bells);
+        let course_counts_size = alloc_size(data.query.course_head_counts.len() * size_of::<u32>());
+        let method_counts_size = alloc_size(num_method_counts * size_of::<usize>());
+
+        let prefix_size = size_of::<Self>()
+            + alloc_size(size_of::<PrefixInner>())
+            + alloc_size(size_of::<CompPath>() + 2 * size_of::<usize>()) // `Arc` of the path
+            + unreachable_size
+            + place_bells_size
+            + course_counts_size
+            + method_counts_size;
+        let table_entry_size = size_of::<StateKey>()
+            + size_of::<Vec<(u32, Score)>>()
+            + alloc_size(size_of::<(u32, Score)>())
+            + size_of::<u64>() // Hash table overhead
+            + unreachable_size
+            + place_bells_size
+            + course_counts_size
+            + method_counts_size;
+        prefix_size
+            + match data.transposition {
+                Transposition::Off => 0,
+                Transposition::Exact | Transposition::Dominance => table_entry_size,
+            }
+    }
+
+    /// The score generated by this prefix so far
+    pub(super) fn score(&self) -> Score {
+        self.inner.score
+    }
+
+    /// The value used to order this prefix in the frontier
+    pub(super) fn priority(&self) -> Score {
+        self.priority
+    }
+
+    /// The parts of this prefix which determine how it can be extended.  If `include_length` is
+    /// `false`, then the length (and anything determined by it) is omitted.
+    pub(super) fn state_key(&self, include_length: bool, data: &SearchData) -> StateKey {
+        let inner = &*self.inner;
+        let is_spliced = data.query.layout.num_methods() > 1;
+        StateKey {
+            node_idx: inner.node_idx,
+            length: include_length.then(|| self.length),
+            len_since_non_duffer: inner.len_since_non_duffer,
+            method_counts: (include_length || is_spliced).then(|| inner.method_counts.clone()),
+            unreachable_nodes: inner.unreachable_nodes.clone(),
+            place_bells: inner.place_bells.clone(),
+            changes_of_method: inner.changes_of_method,
+            required_rows: inner.required_rows,
+            course_counts: inner.course_counts.clone(),
+            rotation: inner.rotation,
+            next_stroke: Stroke::of_row((self.length / data.num_parts as u32) as usize),
+        }
+    }
+
+    /// Returns every way of extending this prefix by one node
+    pub(super) fn successors<'d>(
+        &'d self,
+        data: &'d SearchData,
+    ) -> impl Iterator<Item = Self> + 'd {
+        self.successors_with_links(data)
+            .map(|(_link, prefix)| prefix)
+    }
+
+    /// Returns every way of extending this prefix by one node, along with the [`Link`] taken to
+    /// reach that node
+    pub(super) fn successors_with_links<'d>(
+        &'d self,
+        data: &'d SearchData,
+    ) -> impl Iterator<Item = (&'d Link, Self)> + 'd {
+        let path = Arc::new(self.inner.path.clone());
+        data.graph
+            .succs(self.inner.node_idx)
+            .iter()
+            .filter_map(move |link| Some((link, self.extend(link, &path, data)?)))
+    }
+
+    /// Extend this prefix by taking a given [`Link`] from its last node, returning `None` if the
+    /// resulting prefix can't lead to a valid composition.  `path` is `self`'s path, wrapped in an
+    /// [`Arc`] so that it can be shared between all the successors of `self`.
+    fn extend(&self, link: &Link, path: &Arc<CompPath>, data: &SearchData) -> Option<Self> {
+        let inner = &*self.inner;
+        let next_idx = link.next_node;
+        let succ_node = &data.graph.nodes[next_idx];
+        // All successors start at the row after this node, so they all start at the same stroke
+        let succ_stroke = Stroke::of_row((self.length / data.num_parts as u32) as usize);
+
+        let rotation = (inner.rotation + link.rot) % data.num_parts;
+        let length = self.length + succ_node.length;
+        let score = inner.score + *succ_node.score.get(succ_stroke) + *link.score.get(succ_stroke);
+        let mut method_counts = inner.method_counts.clone();
+        method_counts += data.graph.method_counts(next_idx);
+        let len_since_non_duffer = if succ_node.duffer {
+            inner.len_since_non_duffer + succ_node.length
+        } else {
+            0 // Reset the counter whenever we encounter a non-duffer node
+        };
+        let changes_of_method = if link.is_splice {
+            // Each splice is rung once in every part
+            inner.changes_of_method + data.num_parts as u32
+        } else {
+            inner.changes_of_method
+        };
+
+        if length + succ_node.dist_to_rounds >= data.len_range.end {
+            return None; // Node would make comp too long
+        }
+        if len_since_non_duffer + succ_node.dist_to_non_duffer >= data.max_duffer_rows {
+            return None; // Can't get to a non-duffer fast enough
+        }
+        if changes_of_method > data.max_com {
+            return None; // Too many changes of method
+        }
+        if *succ_node.forbidden.get(succ_stroke) {
+            return None; // Node contains a forbidden row at this stroke
+        }
+        // Moving to a new course head counts a new course
+        let course_counts = if link.is_call {
+            let new_counts = inner
+                .course_counts
+                .iter()
+                .zip_eq(&succ_node.course_head_matches)
+                .map(|(count, matches)| count + matches)
+                .collect_vec();
+            let too_many_courses = new_counts
+                .iter()
+                .zip_eq(&data.course_count_ranges)
+                .any(|(count, range)| count > range.end());
+            if too_many_courses {
+                return None; // Too many courses matching one of the masks
+            }
+            new_counts
+        } else {
+            inner.course_counts.clone()
+        };
+        if inner.unreachable_nodes.contains(next_idx, data.graph) {
+            return None; // Node is false against something already in the comp
+        }
+        if !method_counts.is_feasible(
+            (data.len_range.end - length) as usize,
+            data.query.method_count_range.clone(),
+        ) {
+            return None; // Can't recover the method balance before running out of rows
+        }
+
+        // Compute which nodes are unreachable after this node has been added
+        let unreachable_nodes = inner.unreachable_nodes.with_node(next_idx, data.graph);
+        // Add any new place bells, rewarding the progress towards ATW
+        let mut place_bells = inner.place_bells.clone();
+        place_bells.or(&succ_node.place_bells);
+        let num_new_place_bells = count_ones(&place_bells) - count_ones(&inner.place_bells);
+        let score = score + data.query.atw_weight * num_new_place_bells as f32;
+        let required_rows = inner.required_rows | succ_node.required_rows.get(succ_stroke);
+
+        Some(Self::new(
+            PrefixInner {
+                path: CompPath::Cons(path.clone(), link.source_idx, next_idx),
+                node_idx: next_idx,
+                unreachable_nodes,
+                place_bells,
+                rotation,
+                len_since_non_duffer,
+                changes_of_method,
+                required_rows,
+                course_counts,
+                score,
+                method_counts,
+            },
+            length,
+            data,
+        ))
+    }
+}
+
+/////////////////
+// SERIALISING //
+/////////////////
+
+impl CompPrefix {
+    /// Write `self` to `w` in a compact binary format, so that it can be spilled to disk.  The
+    /// path is flattened, so prefixes which are read back will no longer share their paths.
+    pub(super) fn write_to(&self, w: &mut impl Write) -> io::Result<()> {
+        let inner = &*self.inner;
+        write_u32(w, self.length)?;
+        // Path
+        let (start_idx, links) = inner.path.links();
+        write_u64(w, start_idx.index() as u64)?;
+        write_u64(w, links.len() as u64)?;
+        for (link_idx, node_idx) in links {
+            write_u64(w, link_idx.index() as u64)?;
+            write_u64(w, node_idx.index() as u64)?;
+        }
+        // State
+        write_u64(w, inner.node_idx.index() as u64)?;
+        inner.unreachable_nodes.write_to(w)?;
+        write_bit_vec(w, &inner.place_bells)?;
+        write_u32(w, inner.len_since_non_duffer)?;
+        write_u32(w, inner.changes_of_method)?;
+        write_u64(w, inner.required_rows)?;
+        write_u64(w, inner.course_counts.len() as u64)?;
+        for &count in &inner.course_counts {
+            write_u32(w, count)?;
+        }
+        write_u32(w, inner.rotation as u32)?;
+        write_u32(w, inner.score.to_bits())?;
+        write_u64(w, inner.method_counts.counts().len() as u64)?;
+        for &count in inner.method_counts.counts() {
+            write_u64(w, count as u64)?;
+        }
+        Ok(())
+    }
+
+    /// Read a prefix which was written with [`CompPrefix::write_to`]
+    pub(super) fn read_from(r: &mut impl Read, data: &SearchData) -> io::Result<Self> {
+        let length = read_u32(r)?;
+        // Path
+        let mut path = CompPath::Start(StartIdx::new(read_u64(r)? as usize));
+        let num_links = read_u64(r)?;
+        for _ in 0..num_links {
+            let link_idx = LinkIdx::new(read_u64(r)? as usize);
+            let node_idx = NodeIdx::new(read_u64(r)? as usize);
+            path = CompPath::Cons(Arc::new(path), link_idx, node_idx);
+        }
+        // State
+        let node_idx = NodeIdx::new(read_u64(r)? as usize);
+        let unreachable_nodes = Unreachable::read_from(r)?;
+        let place_bells = read_bit_vec(r)?;
+        let len_since_non_duffer = read_u32(r)?;
+        let changes_of_method = read_u32(r)?;
+        let required_rows = read_u64(r)?;
+        let num_course_counts = read_u64(r)?;
+        let course_counts = (0..num_course_counts)
+            .map(|_| read_u32(r))
+            .collect::<io::Result<Vec<_>>>()?;
+        let rotation = read_u32(r)? as Rotation;
+        let score = Score::from(f32::from_bits(read_u32(r)?));
+        let num_method_counts = read_u64(r)?;
+        let method_counts = (0..num_method_counts)
+            .map(|_| read_u64(r).map(|c| c as usize))
+            .collect::<io::Result<Vec<_>>>()?;
+
+        Ok(Self::new(
+            PrefixInner {
+                path,
+                node_idx,
+                unreachable_nodes,
+                place_bells,
+                len_since_non_duffer,
+                changes_of_method,
+                required_rows,
+                course_counts,
+                rotation,
+                score,
+                method_counts: RowCounts::from_counts(method_counts),
+            },
+            length,
+            data,
+        ))
+    }
+}
+
+/// Returns the number of `1`s in a [`BitVec`]
+fn count_ones(bit_vec: &BitVec) -> usize {
+    bit_vec.blocks().map(|b| b.count_ones() as usize).sum()
+}
+
+impl PartialEq for CompPrefix {
+    fn eq(&self, other: &Self) -> bool {
+        self.priority == other.priority
+    }
+}
+
+impl Eq for CompPrefix {}
+
+impl PartialOrd for CompPrefix {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for CompPrefix {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.priority.cmp(&other.priority)
+    }
+}
+
+///////////////
+// COMP PATH //
+///////////////
+
+/// A route through the composition graph, stored as a reverse linked list.  This allows for
+/// multiple compositions with the same prefix to share the data for that prefix (even if those
+/// compositions are being expanded by different threads).
+#[derive(Debug, Clone)]
+enum CompPath {
+    /// The start of a composition, along with the index within `Graph::start_nodes` of this
+    /// specific start
+    Start(StartIdx),
+    /// The composition follows the sequence in the [`Arc`], followed by taking the `n`th successor
+    /// to that node.
+    Cons(Arc<Self>, LinkIdx, NodeIdx),
+}
+
+impl CompPath {
+    /// The start index of this path, along with every link taken (and the node it leads to)
+    fn links(&self) -> (StartIdx, Vec<(LinkIdx, NodeIdx)>) {
+        let mut links = Vec::new();
+        let mut path = self;
+        loop {
+            match path {
+                Self::Start(start_idx) => {
+                    links.reverse(); // Links were collected from the end of the path
+                    return (*start_idx, links);
+                }
+                Self::Cons(lhs, link_idx, node_idx) => {
+                    links.push((*link_idx, *node_idx));
+                    path = lhs;
+                }
+            }
+        }
+    }
+
+    /// Flatten `self` into the start index and label, the sequence of links taken, and the music
+    /// generated by the composition
+    fn flatten(
+        &self,
+        graph: &Graph,
+        query: &Query,
+        num_parts: Rotation,
+    ) -> (StartIdx, String, Vec<(LinkIdx, String)>, Breakdown) {
+        let mut links = Vec::new();
+        let mut music = Breakdown::zero(query.music_types.len());
+        let (start_idx, start_node_label, _, _) =
+            self.flatten_recursive(graph, num_parts, &mut links, &mut music);
+        (start_idx, start_node_label, links, music)
+    }
+
+    /// Recursively flatten `self`, returning the start idx and label, along with the index and
+    /// end length of the last node
+    fn flatten_recursive(
+        &self,
+        graph: &Graph,
+        num_parts: Rotation,
+        out: &mut Vec<(LinkIdx, String)>,
+        music: &mut Breakdown,
+    ) -> (StartIdx, String, NodeIdx, u32) {
+        match self {
+            Self::Start(start_idx) => {
+                let (start_node_idx, _, _) = graph
+                    .starts
+                    .iter()
+                    .find(|(_, start_idx_2, _)| start_idx == start_idx_2)
+                    .unwrap();
+                let start_node = &graph.nodes[*start_node_idx];
+                // The composition always starts at backstroke
+                *music += graph.node_music(*start_node_idx, Stroke::Back);
+                let label = graph.node_label(*start_node_idx).to_owned();
+                (*start_idx, label, *start_node_idx, start_node.length)
+            }
+            Self::Cons(lhs, link, node_idx) => {
+                let (start_idx, start_label, prev_node_idx, length) =
+                    lhs.flatten_recursive(graph, num_parts, out, music);
+                let stroke = Stroke::of_row((length / num_parts as u32) as usize);
+                // Add music from the join between the two nodes
+                *music += graph
+                    .link_music(prev_node_idx, *link, *node_idx)
+                    .get(stroke);
+                // Add music from the node itself
+                let node = &graph.nodes[*node_idx];
+                *music += graph.node_music(*node_idx, stroke);
+                out.push((*link, graph.node_label(*node_idx).to_owned()));
+                (start_idx, start_label, *node_idx, length + node.length)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bellframe::{Mask, RowBuf, Stage};
+
+    use crate::{
+        search::{Graph, SearchData},
+        test_utils::{config, layout, method, plain_bob, query, run},
+        Comp, FalsenessRepr, OptRange, Priority, Transposition,
+    };
+
+    use super::CompPrefix;
+
+    /// Prefixes which are spilled to disk should be read back exactly as they were written
+    fn check_round_trip(falseness: FalsenessRepr) {
+        let methods = [
+            plain_bob(Stage::MINOR),
+            method("Test Bob", Stage::MINOR, "x14x16x16,12"),
+        ];
+        let mut query = query(layout(&methods, "123456", "123456"), 0..500);
+        query.course_head_counts = vec![(vec![Mask::parse("1xxxx6")], OptRange::default())];
+        query.atw_weight = 0.1;
+        let source_graph = query.unoptimised_graph();
+        let graph = Graph::new(&source_graph, &query, Some(falseness));
+        assert_eq!(graph.falseness_repr, falseness);
+        let data = SearchData::new(&graph, &query, Priority::AvgScore, Transposition::Off);
+
+        // Build prefixes with deeply nested paths (some of which are shared), preferring calls
+        // and splices so that the course and method counts change
+        let mut prefixes = CompPrefix::starts(&data);
+        let mut prefix = prefixes[0].clone();
+        for _ in 0..8 {
+            let succs = prefix.successors_with_links(&data).collect::<Vec<_>>();
+            let next = succs
+                .iter()
+                .find(|(link, _)| link.is_call || link.is_splice)
+                .unwrap_or(&succs[0])
+                .1
+                .clone();
+            prefixes.extend(succs.into_iter().map(|(_link, p)| p));
+            prefix = next;
+        }
+        assert!(prefix.inner.course_counts.iter().any(|&c| c > 1));
+
+        let mut bytes = Vec::new();
+        for p in &prefixes {
+            p.write_to(&mut bytes).unwrap();
+        }
+        let mut reader = bytes.as_slice();
+        for p in &prefixes {
+            let read_prefix = CompPrefix::read_from(&mut reader, &data).unwrap();
+            assert_eq!(format!("{:?}", read_prefix), format!("{:?}", p));
+        }
+        assert!(reader.is_empty());
+    }
+
+    #[test]
+    fn round_trip_dense() {
+        check_round_trip(FalsenessRepr::Dense);
+    }
+
+    #[test]
+    fn round_trip_sparse() {
+        check_round_trip(FalsenessRepr::Sparse);
+    }
+
+    #[test]
+    fn changes_of_method() {
+        let methods = [
+            plain_bob(Stage::MINOR),
+            method("Test Bob", Stage::MINOR, "x14x16x16,12"),
+        ];
+        let comps = |changes_of_method: OptRange| {
+            let mut query = query(layout(&methods, "123456", "123456"), 0..121);
+            query.changes_of_method = changes_of_method;
+            let comps = run(query, config());
+            assert!(!comps.is_empty());
+            comps
+        };
+        let num_methods_used =
+            |c: &Comp| c.method_counts.counts().iter().filter(|&&n| n > 0).count();
+
+        let no_splices = OptRange {
+            min: None,
+            max: Some(0),
+        };
+        for c in comps(no_splices) {
+            assert_eq!(c.changes_of_method, 0);
+            assert_eq!(num_methods_used(&c), 1);
+        }
+        let min_2_splices = OptRange {
+            min: Some(2),
+            max: None,
+        };
+        for c in comps(min_2_splices) {
+            assert!(c.changes_of_method >= 2);
+            assert_eq!(num_methods_used(&c), 2);
+        }
+    }
+
+    /// Every call in a course-wise layout moves to a new course, so bounding the number of
+    /// courses (with any course head) bounds the number of calls
+    #[test]
+    fn course_head_counts() {
+        let num_calls = |count: OptRange| {
+            let mut query = query(
+                layout(&[plain_bob(Stage::MINOR)], "123456", "123456"),
+                0..150,
+            );
+            query.course_head_counts = vec![(vec![Mask::parse("xxxxx6")], count)];
+            let comps = run(query.clone(), config());
+            assert!(!comps.is_empty());
+            comps
+                .iter()
+                .map(|c| {
+                    let links = c.links.iter().map(|(idx, _)| &query.layout.links[*idx]);
+                    links.filter(|l| l.is_call()).count()
+                })
+                .collect::<Vec<_>>()
+        };
+        let max_2_courses = OptRange {
+            min: None,
+            max: Some(2),
+        };
+        assert!(num_calls(max_2_courses).iter().all(|&n| n <= 1));
+        let min_3_courses = OptRange {
+            min: Some(3),
+            max: None,
+        };
+        assert!(num_calls(min_3_courses).iter().all(|&n| n >= 2));
+    }
+
+    /// Node scores assume that every part starts at the same stroke, so multi-part compositions
+    /// can't have odd-length parts
+    #[test]
+    fn even_part_lengths() {
+        let mut query = query(
+            layout(&[plain_bob(Stage::MINOR)], "123456", "123456"),
+            0..300,
+        );
+        query.part_head = RowBuf::parse("132456").unwrap();
+        let comps = run(query, config());
+        assert!(!comps.is_empty());
+        assert!(comps.iter().all(|c| (c.length / 2) % 2 == 0));
+    }
+}
@@ -0,0 +1,209 @@
+//! Spilling parts of the search frontier to temporary files on disk, so that large searches don't
+//! have to discard prefixes when the frontier gets too large.
+
+use std::{
+    fs::File,
+    io::{self, BufReader, BufWriter, Read, Write},
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use bit_vec::BitVec;
+use log::log;
+
+use crate::music::Score;
+
+use super::{prefix::CompPrefix, SearchData};
+
+/// Counter used to give every spill file a unique name, even across threads
+static NEXT_FILE_IDX: AtomicUsize = AtomicUsize::new(0);
+
+/// A set of files, each containing a 'run' of prefixes which have been spilled from the frontier
+#[derive(Debug)]
+pub(super) struct SpillFiles {
+    dir: PathBuf,
+    runs: Vec<Run>,
+}
+
+/// A single file of spilled prefixes
+#[derive(Debug)]
+struct Run {
+    path: PathBuf,
+    num_prefixes: usize,
+    /// The highest priority of any prefix in this run
+    best_priority: Score,
+}
+
+/// Create `dir` if it doesn't already exist, and check that files can be written to it.  This is
+/// run before the search starts, so that an unusable directory is reported straight away rather
+/// than when the frontier first fills up.
+pub(crate) fn prepare_spill_dir(dir: &Path) -> io::Result<()> {
+    std::fs::create_dir_all(dir)?;
+    let path = dir.join(format!("monument-probe-{}.bin", std::process::id()));
+    File::create(&path)?;
+    std::fs::remove_file(&path)
+}
+
+impl SpillFiles {
+    pub(super) fn new(dir: &Path) -> Self {
+        Self {
+            dir: dir.to_owned(),
+            runs: Vec::new(),
+        }
+    }
+
+    /// Write some prefixes to a new file
+    pub(super) fn spill(&mut self, prefixes: Vec<CompPrefix>) -> io::Result<()> {
+        let best_priority = match prefixes.iter().max() {
+            Some(p) => p.priority(),
+            None => return Ok(()), // Nothing to spill
+        };
+        let file_idx = NEXT_FILE_IDX.fetch_add(1, Ordering::Relaxed);
+        let path = self.dir.join(format!(
+            "monument-frontier-{}-{}.bin",
+            std::process::id(),
+            file_idx
+        ));
+
+        let mut writer = BufWriter::new(File::create(&path)?);
+        for p in &prefixes {
+            p.write_to(&mut writer)?;
+        }
+        writer.flush()?;
+
+        log::debug!("Spilled {} prefixes to {:?}", prefixes.len(), path);
+        self.runs.push(Run {
+            path,
+            num_prefixes: prefixes.len(),
+            best_priority,
+        });
+        Ok(())
+    }
+
+    /// The highest priority of any spilled prefix, or `None` if no prefixes are spilled
+    pub(super) fn best_priority(&self) -> Option<Score> {
+        self.runs.iter().map(|r| r.best_priority).max()
+    }
+
+    /// The total number of prefixes which are stored on disk
+    pub(super) fn len(&self) -> usize {
+        self.runs.iter().map(|r| r.num_prefixes).sum()
+    }
+
+    /// Remove the run containing the best spilled prefix, returning all its prefixes
+    pub(super) fn reload_best(&mut self, data: &SearchData) -> io::Result<Vec<CompPrefix>> {
+        let run_idx = match self
+            .runs
+            .iter()
+            .enumerate()
+            .max_by_key(|(_, r)| r.best_priority)
+        {
+            Some((idx, _)) => idx,
+            None => return Ok(Vec::new()),
+        };
+        let run = self.runs.remove(run_idx);
+
+        let mut reader = BufReader::new(File::open(&run.path)?);
+        let mut prefixes = Vec::with_capacity(run.num_prefixes);
+        for _ in 0..run.num_prefixes {
+            prefixes.push(CompPrefix::read_from(&mut reader, data)?);
+        }
+        drop(reader);
+        std::fs::remove_file(&run.path)?;
+
+        log::debug!("Reloaded {} prefixes from {:?}", prefixes.len(), run.path);
+        Ok(prefixes)
+    }
+}
+
+impl Drop for SpillFiles {
+    fn drop(&mut self) {
+        // Clean up any files which were never reloaded
+        for run in &self.runs {
+            if let Err(e) = std::fs::remove_file(&run.path) {
+                log::warn!("Couldn't remove spill file {:?}: {}", run.path, e);
+            }
+        }
+    }
+}
+
+/////////////////////
+// BINARY ENCODING //
+/////////////////////
+
+pub(super) fn write_u32(w: &mut impl Write, v: u32) -> io::Result<()> {
+    w.write_all(&v.to_le_bytes())
+}
+
+pub(super) fn write_u64(w: &mut impl Write, v: u64) -> io::Result<()> {
+    w.write_all(&v.to_le_bytes())
+}
+
+pub(super) fn write_bit_vec(w: &mut impl Write, bits: &BitVec) -> io::Result<()> {
+    write_u64(w, bits.len() as u64)?;
+    w.write_all(&bits.to_bytes())
+}
+
+pub(super) fn read_u32(r: &mut impl Read) -> io::Result<u32> {
+    let mut bytes = [0; 4];
+    r.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+pub(super) fn read_u64(r: &mut impl Read) -> io::Result<u64> {
+    let mut bytes = [0; 8];
+    r.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+pub(super) fn read_bit_vec(r: &mut impl Read) -> io::Result<BitVec> {
+    let len = read_u64(r)? as usize;
+    let mut bytes = vec![0; (len + 7) / 8];
+    r.read_exact(&mut bytes)?;
+    let mut bits = BitVec::from_bytes(&bytes);
+    bits.truncate(len);
+    Ok(bits)
+}
+
+#[cfg(test)]
+mod tests {
+    use bellframe::Stage;
+
+    use crate::{
+        test_utils::{config, layout, plain_bob, query, run, runs},
+        Config,
+    };
+
+    /// Spilled prefixes are reloaded rather than discarded, so a search with a tiny queue should
+    /// find the same compositions as one which never has to spill
+    #[test]
+    fn spilling_finds_same_comps() {
+        let mut query = query(
+            layout(&[plain_bob(Stage::MINOR)], "123456", "123456"),
+            0..200,
+        );
+        query.num_comps = 5;
+        query.music_types = vec![runs(Stage::MINOR, 4)];
+
+        let spill_dir =
+            std::env::temp_dir().join(format!("monument-spill-test-{}", std::process::id()));
+        let best_scores = |queue_limit: usize, spill: bool| {
+            let comps = run(
+                query.clone(),
+                Config {
+                    queue_limit,
+                    spill_dir: spill.then(|| spill_dir.clone()),
+                    exact: true,
+                    ..config()
+                },
+            );
+            comps.iter().map(|c| c.avg_score).collect::<Vec<_>>()
+        };
+        let unbounded_scores = best_scores(usize::MAX, false);
+        let spilled_scores = best_scores(20, true);
+        std::fs::remove_dir_all(&spill_dir).unwrap();
+
+        assert_eq!(unbounded_scores.len(), 5);
+        assert_eq!(spilled_scores, unbounded_scores);
+    }
+}
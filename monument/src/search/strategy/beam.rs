@@ -0,0 +1,81 @@
+use std::{mem, sync::Arc};
+
+use log::log;
+
+use crate::{
+    search::{prefix::CompPrefix, transposition::TranspositionTable, WorkPool},
+    Comp,
+};
+
+use super::{SearchData, SearchStrategy};
+
+/// Beam search: extend every prefix in the beam by one node, then keep only the best `width`
+/// prefixes (according to their [`Priority`](crate::Priority)).  This uses bounded memory but,
+/// like best-first search, isn't guaranteed to find the best compositions.
+///
+/// When running on multiple threads, the start nodes are shared between the threads and each
+/// thread keeps its own beam.
+#[derive(Debug, Clone)]
+pub(super) struct Beam {
+    width: usize,
+    /// The maximum number of states stored in the [`TranspositionTable`]
+    table_capacity: usize,
+    thread_idx: usize,
+    pool: Arc<WorkPool>,
+}
+
+impl Beam {
+    pub(super) fn new(
+        width: usize,
+        table_capacity: usize,
+        thread_idx: usize,
+        pool: Arc<WorkPool>,
+    ) -> Self {
+        assert!(width > 0, "Beam width must be positive");
+        Self {
+            width,
+            table_capacity,
+            thread_idx,
+            pool,
+        }
+    }
+}
+
+impl SearchStrategy for Beam {
+    fn search(&mut self, data: &SearchData, comp_fn: &mut dyn FnMut(Comp) -> bool) {
+        let mut beam = CompPrefix::starts(data)
+            .into_iter()
+            .skip(self.thread_idx)
+            .step_by(self.pool.num_threads())
+            .collect::<Vec<_>>();
+        let mut next_beam = Vec::new();
+        let mut table = TranspositionTable::new(data, self.table_capacity);
+        let mut depth = 0;
+        while !beam.is_empty() {
+            if self.pool.is_finished() {
+                return; // Another thread has found enough comps
+            }
+            for prefix in beam.drain(..) {
+                if !prefix.is_complete(data) {
+                    next_beam.extend(prefix.successors(data).filter(|p| table.check(p, data)));
+                    continue;
+                }
+                // Prefix has come round
+                if let Some(comp) = prefix.to_comp(data) {
+                    if comp_fn(comp) && self.pool.add_comp() >= data.query.num_comps {
+                        self.pool.finish();
+                        return; // Stop the search once we've got enough comps
+                    }
+                }
+            }
+
+            // Only keep the best prefixes
+            next_beam.sort_unstable_by(|a, b| b.cmp(a));
+            next_beam.truncate(self.width);
+            mem::swap(&mut beam, &mut next_beam);
+
+            depth += 1;
+            log::debug!("Beam depth {}: {} prefixes", depth, beam.len());
+        }
+    }
+}
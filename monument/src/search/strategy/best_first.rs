@@ -0,0 +1,238 @@
+use std::{collections::BinaryHeap, mem::size_of, path::PathBuf, sync::Arc};
+
+use log::log;
+
+use crate::{
+    search::{prefix::CompPrefix, spill::SpillFiles, transposition::TranspositionTable, WorkPool},
+    Comp,
+};
+
+use super::{SearchData, SearchStrategy};
+
+/// Repeatedly expand the prefix with the highest [`Priority`](crate::Priority), halving the size
+/// of the frontier whenever it reaches `queue_limit`.  If `spill_dir` is set, then the worst half
+/// of the frontier is written to a file in `spill_dir` (to be reloaded once the prefixes in memory
+/// are worse than those on disk), otherwise it is discarded.
+///
+/// All the threads share a [`WorkPool`]: whenever a thread runs out of prefixes, the other threads
+/// give it some of their best prefixes.
+///
+/// If `memory_limit` is set, then `queue_limit` is reduced so that the frontier uses at most
+/// roughly `memory_limit` bytes.
+///
+/// If `exact` is `true`, then the search won't stop until it has proved that it's found the best
+/// [`Query::num_comps`](crate::Query::num_comps) compositions (which are only passed to `comp_fn`
+/// once every thread has finished).  Any prefix which can't beat the worst of the best
+/// compositions found so far by any thread is pruned.
+#[derive(Debug, Clone)]
+pub(super) struct BestFirst {
+    queue_limit: usize,
+    memory_limit: Option<usize>,
+    exact: bool,
+    spill_dir: Option<PathBuf>,
+    /// Only the first thread starts with any prefixes; the others wait for prefixes to be shared
+    is_first_thread: bool,
+    pool: Arc<WorkPool>,
+}
+
+/// The maximum number of prefixes given to an idle thread at once
+const MAX_SHARED_PREFIXES: usize = 1_000;
+
+impl BestFirst {
+    pub(super) fn new(
+        queue_limit: usize,
+        memory_limit: Option<usize>,
+        exact: bool,
+        spill_dir: Option<PathBuf>,
+        thread_idx: usize,
+        pool: Arc<WorkPool>,
+    ) -> Self {
+        Self {
+            queue_limit,
+            memory_limit,
+            exact,
+            spill_dir,
+            is_first_thread: thread_idx == 0,
+            pool,
+        }
+    }
+
+    /// The maximum number of prefixes in the frontier, taking `memory_limit` into account
+    fn effective_queue_limit(&self, data: &SearchData) -> usize {
+        let memory_limit = match self.memory_limit {
+            Some(m) => m,
+            None => return self.queue_limit,
+        };
+        let prefix_size = CompPrefix::size_estimate(data);
+        // Leave space for the `Vec` which stores the heap, and the spare capacity it might have
+        let max_prefixes = memory_limit / (prefix_size + 2 * size_of::<CompPrefix>());
+        log::info!(
+            "Each prefix takes roughly {} bytes, so the memory limit allows {} prefixes",
+            prefix_size,
+            max_prefixes
+        );
+        // Always allow enough prefixes to make progress
+        self.queue_limit.min(max_prefixes).max(2)
+    }
+}
+
+impl SearchStrategy for BestFirst {
+    fn search(&mut self, data: &SearchData, comp_fn: &mut dyn FnMut(Comp) -> bool) {
+        let exact = self.exact;
+        let queue_limit = self.effective_queue_limit(data);
+        let pool = self.pool.clone();
+
+        // Initialise the frontier to just the start nodes (or the fixed prefix, if there is one)
+        let mut frontier = if self.is_first_thread {
+            BinaryHeap::from(CompPrefix::starts(data))
+        } else {
+            BinaryHeap::new()
+        };
+        // The table can store as many states as the frontier can store prefixes, so both are
+        // bounded by `memory_limit`
+        let mut table = TranspositionTable::new(data, queue_limit);
+        let mut spill_files = self.spill_dir.as_deref().map(SpillFiles::new);
+
+        let mut is_truncated = false;
+
+        // Repeatedly choose the best prefix and expand it (i.e. add each way of extending it to
+        // the frontier).
+        let mut iter_count = 0;
+        loop {
+            if pool.is_finished() {
+                break; // Another thread has found enough comps
+            }
+
+            // If the best prefix has been spilled to disk, then load it back into memory
+            if let Some(files) = &mut spill_files {
+                if files.best_priority() > frontier.peek().map(CompPrefix::priority) {
+                    match files.reload_best(data) {
+                        Ok(prefixes) => frontier.extend(prefixes),
+                        Err(e) => {
+                            log::error!("Error reloading spilled prefixes: {}", e);
+                            is_truncated = true; // The spilled prefixes have been lost
+                            spill_files = None;
+                        }
+                    }
+                }
+            }
+
+            let prefix = match frontier.pop() {
+                Some(p) => p,
+                // If this thread has run out of prefixes, then take some from the other threads
+                None => match pool.take() {
+                    Some(prefixes) => {
+                        frontier.extend(prefixes);
+                        continue;
+                    }
+                    None => break, // Every prefix has been expanded
+                },
+            };
+            if exact && prefix.avg_score_bound(data) <= pool.score_to_beat() {
+                continue; // Prefix can't beat the comps we've already found
+            }
+
+            // Check if the comp has come round
+            if prefix.is_complete(data) {
+                if let Some(comp) = prefix.to_comp(data) {
+                    if exact {
+                        pool.add_best_comp(comp);
+                        continue;
+                    }
+
+                    if comp_fn(comp) && pool.add_comp() >= data.query.num_comps {
+                        pool.finish();
+                        break; // Stop the search once we've got enough comps
+                    }
+                }
+                continue; // Don't expand comps after they've come round
+            }
+
+            // Expand this node
+            let score_to_beat = pool.score_to_beat();
+            frontier.extend(prefix.successors(data).filter(|p| {
+                (!exact || p.avg_score_bound(data) > score_to_beat) && table.check(p, data)
+            }));
+
+            // If another thread has run out of work, then give it some of our best prefixes
+            if pool.wants_work() && frontier.len() > 1 {
+                pool.share(take_shared_prefixes(&mut frontier));
+            }
+
+            // If the queue gets too long, then halve its size
+            if frontier.len() >= queue_limit {
+                let worst_prefixes = split_heap(&mut frontier, queue_limit / 2);
+                match &mut spill_files {
+                    Some(files) => {
+                        if let Err(e) = files.spill(worst_prefixes) {
+                            log::error!("Error spilling prefixes, so discarding them: {}", e);
+                            is_truncated = true;
+                        }
+                    }
+                    None => {
+                        log::debug!("Truncating queue");
+                        is_truncated = true;
+                    }
+                }
+            }
+
+            // Print stats every so often
+            iter_count += 1;
+            if iter_count % 1_000_000 == 0 {
+                let mut total_len = 0;
+                let mut max_len = 0;
+                frontier.iter().for_each(|n| {
+                    total_len += n.length as usize;
+                    max_len = max_len.max(n.length);
+                });
+
+                log::info!(
+                    "{} iters, {} items in queue ({} on disk), avg/max len {:.0}/{}, {} states",
+                    iter_count,
+                    frontier.len(),
+                    spill_files.as_ref().map_or(0, SpillFiles::len),
+                    total_len as f32 / frontier.len() as f32,
+                    max_len,
+                    table.len()
+                );
+            }
+        }
+
+        if exact {
+            if is_truncated {
+                log::warn!("Queue was truncated, so the best compositions may not have been found");
+            } else {
+                log::info!("Search was exhaustive, so these are the best possible compositions");
+            }
+            // The best comps are shared between the threads, so only the last thread to finish
+            // passes them on
+            for comp in pool.finish_exhaustive().into_iter().flatten() {
+                comp_fn(comp);
+            }
+        }
+    }
+}
+
+/// Remove every other one of the best prefixes from `frontier`, so that both this thread and the
+/// thread receiving the prefixes can keep working on good prefixes
+fn take_shared_prefixes(frontier: &mut BinaryHeap<CompPrefix>) -> Vec<CompPrefix> {
+    let num_to_share = (frontier.len() / 2).min(MAX_SHARED_PREFIXES);
+    let mut kept = Vec::with_capacity(num_to_share);
+    let mut shared = Vec::with_capacity(num_to_share);
+    for _ in 0..num_to_share {
+        kept.extend(frontier.pop());
+        shared.extend(frontier.pop());
+    }
+    frontier.extend(kept);
+    shared
+}
+
+/// Truncate `heap_ref` to its best `len` elements, returning the elements which were removed
+fn split_heap<T: Ord>(heap_ref: &mut BinaryHeap<T>, len: usize) -> Vec<T> {
+    let heap = std::mem::take(heap_ref);
+    let mut nodes = heap.into_vec();
+    nodes.sort_by(|a, b| b.cmp(a)); // Sort highest score first
+    let removed = nodes.split_off(len.min(nodes.len()));
+    *heap_ref = BinaryHeap::from(nodes);
+    removed
+}
@@ -0,0 +1,81 @@
+use std::sync::Arc;
+
+use itertools::Itertools;
+use log::log;
+
+use crate::{
+    search::{prefix::CompPrefix, transposition::TranspositionTable, WorkPool},
+    Comp,
+};
+
+use super::{SearchData, SearchStrategy};
+
+/// Depth-first branch-and-bound.  This explores every composition (most promising successors
+/// first), pruning any prefix which can't beat the worst of the best compositions found so far.
+/// This uses very little memory and is always exhaustive, so the compositions are only passed to
+/// `comp_fn` once the search has finished and are guaranteed to be the best possible.
+///
+/// When running on multiple threads, the start nodes are shared between the threads and the best
+/// compositions are shared through the [`WorkPool`], so the threads prune each other's prefixes.
+#[derive(Debug, Clone)]
+pub(super) struct DepthFirst {
+    /// The maximum number of states stored in the [`TranspositionTable`]
+    table_capacity: usize,
+    thread_idx: usize,
+    pool: Arc<WorkPool>,
+}
+
+impl DepthFirst {
+    pub(super) fn new(table_capacity: usize, thread_idx: usize, pool: Arc<WorkPool>) -> Self {
+        Self {
+            table_capacity,
+            thread_idx,
+            pool,
+        }
+    }
+}
+
+impl SearchStrategy for DepthFirst {
+    fn search(&mut self, data: &SearchData, comp_fn: &mut dyn FnMut(Comp) -> bool) {
+        let mut table = TranspositionTable::new(data, self.table_capacity);
+        let starts = CompPrefix::starts(data);
+        for start in starts
+            .into_iter()
+            .skip(self.thread_idx)
+            .step_by(self.pool.num_threads())
+        {
+            explore(start, data, &self.pool, &mut table);
+        }
+        // The best comps are shared between the threads, so only the last thread to finish passes
+        // them on
+        if let Some(comps) = self.pool.finish_exhaustive() {
+            log::info!("Search was exhaustive, so these are the best possible compositions");
+            for comp in comps {
+                comp_fn(comp);
+            }
+        }
+    }
+}
+
+fn explore(prefix: CompPrefix, data: &SearchData, pool: &WorkPool, table: &mut TranspositionTable) {
+    if prefix.avg_score_bound(data) <= pool.score_to_beat() {
+        return; // Prefix can't beat the comps we've already found
+    }
+    if !table.check(&prefix, data) {
+        return; // An equivalent prefix has already been explored
+    }
+    if prefix.is_complete(data) {
+        if let Some(comp) = prefix.to_comp(data) {
+            pool.add_best_comp(comp);
+        }
+        return; // Don't expand comps after they've come round
+    }
+
+    // Explore the most promising successors first, so that good comps are found early and the
+    // pruning gets tighter as quickly as possible
+    let mut succs = prefix.successors(data).collect_vec();
+    succs.sort_unstable_by(|a, b| b.cmp(a));
+    for succ in succs {
+        explore(succ, data, pool, table);
+    }
+}
@@ -0,0 +1,62 @@
+use log::log;
+
+use crate::{search::prefix::CompPrefix, Comp};
+
+use super::{SearchData, SearchStrategy};
+
+/// Exhaustive enumeration: walk the entire graph depth-first and pass _every_ composition to
+/// `comp_fn` as soon as it's found.  Prefixes are still pruned if they're false or can't reach
+/// rounds within the length range, but no prefix is ever pruned for its score.  This ignores
+/// both [`Config::queue_limit`](crate::Config::queue_limit) and
+/// [`Query::num_comps`](crate::Query::num_comps), so is only feasible for small searches.
+///
+/// When running on multiple threads, the start nodes are shared between the threads so that
+/// every composition is only generated once.
+#[derive(Debug, Clone)]
+pub(super) struct Enumerate {
+    thread_idx: usize,
+    num_threads: usize,
+}
+
+impl Enumerate {
+    pub(super) fn new(thread_idx: usize, num_threads: usize) -> Self {
+        Self {
+            thread_idx,
+            num_threads,
+        }
+    }
+}
+
+impl SearchStrategy for Enumerate {
+    fn search(&mut self, data: &SearchData, comp_fn: &mut dyn FnMut(Comp) -> bool) {
+        let mut num_comps = 0;
+        let starts = CompPrefix::starts(data);
+        for start in starts
+            .into_iter()
+            .skip(self.thread_idx)
+            .step_by(self.num_threads)
+        {
+            explore(start, data, comp_fn, &mut num_comps);
+        }
+        log::info!("Enumerated {} compositions", num_comps);
+    }
+}
+
+fn explore(
+    prefix: CompPrefix,
+    data: &SearchData,
+    comp_fn: &mut dyn FnMut(Comp) -> bool,
+    num_comps: &mut usize,
+) {
+    if prefix.is_complete(data) {
+        if let Some(comp) = prefix.to_comp(data) {
+            if comp_fn(comp) {
+                *num_comps += 1;
+            }
+        }
+        return; // Don't expand comps after they've come round
+    }
+    for succ in prefix.successors(data) {
+        explore(succ, data, comp_fn, num_comps);
+    }
+}
@@ -0,0 +1,221 @@
+//! Different algorithms for exploring the lowered search [`Graph`](super::Graph).
+
+use std::{path::PathBuf, sync::Arc};
+
+use crate::{utils::Rng, Comp, Strategy};
+
+use super::{SearchData, WorkPool};
+
+mod beam;
+mod best_first;
+mod depth_first;
+mod enumerate;
+mod random;
+
+use beam::Beam;
+use best_first::BestFirst;
+use depth_first::DepthFirst;
+use enumerate::Enumerate;
+use random::RandomRestarts;
+
+/// An algorithm for finding compositions in the lowered search [`Graph`](super::Graph)
+pub(crate) trait SearchStrategy {
+    /// Run the search, passing every composition found to `comp_fn`.  `comp_fn` returns `false`
+    /// if the composition was rejected, in which case it doesn't count towards
+    /// [`Query::num_comps`](crate::Query::num_comps).
+    fn search(&mut self, data: &SearchData, comp_fn: &mut dyn FnMut(Comp) -> bool);
+}
+
+/// Create the [`SearchStrategy`] corresponding to a [`Strategy`].  `thread_idx` and `pool` are
+/// used to make sure that each thread doesn't find the same compositions, either by sharing
+/// prefixes between the threads or by giving each thread a different subset of the start nodes.
+/// [`Strategy::RandomRestarts`] is the exception: each thread walks with a different seed, so
+/// threads can occasionally find the same composition.
+pub(crate) fn new(
+    strategy: Strategy,
+    queue_limit: usize,
+    memory_limit: Option<usize>,
+    exact: bool,
+    spill_dir: Option<PathBuf>,
+    thread_idx: usize,
+    pool: Arc<WorkPool>,
+) -> Box<dyn SearchStrategy> {
+    match strategy {
+        Strategy::BestFirst => Box::new(BestFirst::new(
+            queue_limit,
+            memory_limit,
+            exact,
+            spill_dir,
+            thread_idx,
+            pool,
+        )),
+        Strategy::DepthFirst => Box::new(DepthFirst::new(queue_limit, thread_idx, pool)),
+        Strategy::Enumerate => Box::new(Enumerate::new(thread_idx, pool.num_threads())),
+        Strategy::Beam { width } => Box::new(Beam::new(width, queue_limit, thread_idx, pool)),
+        Strategy::RandomRestarts { num_restarts, seed } => Box::new(RandomRestarts::new(
+            num_restarts,
+            Rng::new(seed.wrapping_add(thread_idx as u64)),
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bellframe::Stage;
+
+    use crate::{
+        music::Score,
+        test_utils::{comp_strings, config, layout, plain_bob, query, run, runs},
+        Config, Priority, Query, Strategy,
+    };
+
+    fn test_query() -> Query {
+        let mut query = query(
+            layout(&[plain_bob(Stage::MINOR)], "123456", "123456"),
+            0..200,
+        );
+        query.num_comps = 10;
+        query.music_types = vec![runs(Stage::MINOR, 4)];
+        query
+    }
+
+    /// The average scores of the compositions found by a search, worst first
+    fn scores(query: &Query, config: Config) -> Vec<Score> {
+        let comps = run(query.clone(), config);
+        comps.iter().map(|c| c.avg_score).collect()
+    }
+
+    /// The scores of the best compositions, found by an exhaustive depth-first search
+    fn best_scores(query: &Query) -> Vec<Score> {
+        let depth_first = Config {
+            strategy: Strategy::DepthFirst,
+            ..config()
+        };
+        let scores = scores(query, depth_first);
+        assert_eq!(scores.len(), query.num_comps);
+        scores
+    }
+
+    #[test]
+    fn exact_best_first() {
+        let query = test_query();
+        let exact = Config {
+            exact: true,
+            ..config()
+        };
+        assert_eq!(scores(&query, exact), best_scores(&query));
+    }
+
+    /// Priorities only change the order in which prefixes are expanded, so exact searches should
+    /// find the same compositions whichever priority is used
+    #[test]
+    fn priorities() {
+        let query = test_query();
+        let best_scores = best_scores(&query);
+        for &priority in &[
+            Priority::AvgScore,
+            Priority::Estimated,
+            Priority::UpperBound,
+        ] {
+            let exact = Config {
+                exact: true,
+                priority,
+                ..config()
+            };
+            assert_eq!(scores(&query, exact), best_scores, "{:?}", priority);
+        }
+    }
+
+    /// Inexact strategies can miss the best compositions, but can never find better ones
+    #[test]
+    fn inexact_strategies() {
+        let query = test_query();
+        let best_score = *best_scores(&query).last().unwrap();
+        let strategies = [
+            Strategy::BestFirst,
+            Strategy::Beam { width: 10 },
+            Strategy::RandomRestarts {
+                num_restarts: 200,
+                seed: 0,
+            },
+        ];
+        for &strategy in &strategies {
+            let scores = scores(
+                &query,
+                Config {
+                    strategy,
+                    ..config()
+                },
+            );
+            assert!(!scores.is_empty(), "{:?}", strategy);
+            assert!(scores.iter().all(|&s| s <= best_score), "{:?}", strategy);
+        }
+    }
+
+    /// Enumeration should find exactly the compositions that an unlimited depth-first search
+    /// finds
+    #[test]
+    fn enumerate() {
+        let mut query = test_query();
+        query.len_range = 0..100;
+        query.num_comps = 1_000_000;
+        let all_comps = |strategy: Strategy| {
+            let comps = run(
+                query.clone(),
+                Config {
+                    strategy,
+                    ..config()
+                },
+            );
+            comp_strings(&comps, &query.layout)
+        };
+        let enumerated = all_comps(Strategy::Enumerate);
+        assert!(!enumerated.is_empty());
+        assert!(enumerated.len() < query.num_comps);
+        assert_eq!(enumerated, all_comps(Strategy::DepthFirst));
+    }
+
+    #[test]
+    fn memory_limit() {
+        let query = test_query();
+        let best_scores = best_scores(&query);
+        let best_score = *best_scores.last().unwrap();
+        // A small budget truncates the frontier, so may miss the best compositions
+        let small_budget = Config {
+            memory_limit: Some(64 * 1024),
+            ..config()
+        };
+        let scores_with_small_budget = scores(&query, small_budget);
+        assert!(!scores_with_small_budget.is_empty());
+        assert!(scores_with_small_budget.iter().all(|&s| s <= best_score));
+        // A large budget never truncates the frontier, so doesn't change exact searches
+        let large_budget = Config {
+            memory_limit: Some(1 << 30),
+            exact: true,
+            ..config()
+        };
+        assert_eq!(scores(&query, large_budget), best_scores);
+    }
+
+    /// Threads share prefixes (and their paths) through the work pool, so exact searches should
+    /// find the same compositions on any number of threads
+    #[test]
+    fn multi_threaded() {
+        let query = test_query();
+        let best_scores = best_scores(&query);
+        for &strategy in &[Strategy::BestFirst, Strategy::DepthFirst] {
+            let multi_threaded = Config {
+                num_threads: Some(4),
+                strategy,
+                exact: true,
+                ..config()
+            };
+            assert_eq!(
+                scores(&query, multi_threaded),
+                best_scores,
+                "{:?}",
+                strategy
+            );
+        }
+    }
+}
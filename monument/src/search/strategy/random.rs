@@ -0,0 +1,79 @@
+use std::collections::HashSet;
+
+use itertools::Itertools;
+use log::log;
+
+use crate::{
+    layout::{LinkIdx, StartIdx},
+    search::prefix::CompPrefix,
+    utils::Rng,
+    Comp,
+};
+
+use super::{SearchData, SearchStrategy};
+
+/// Repeatedly build compositions by walking randomly from a start node until the composition
+/// comes round or gets stuck.  At each step, two random successors are chosen and the one with
+/// the higher [`Priority`](crate::Priority) is taken, so the walks are biased towards good
+/// compositions.  Each unique composition found is passed to `comp_fn`.
+#[derive(Debug, Clone)]
+pub(super) struct RandomRestarts {
+    num_restarts: usize,
+    rng: Rng,
+}
+
+impl RandomRestarts {
+    pub(super) fn new(num_restarts: usize, rng: Rng) -> Self {
+        Self { num_restarts, rng }
+    }
+
+    /// Randomly walk from a start to either a composition or a dead end
+    fn walk(&mut self, starts: &[CompPrefix], data: &SearchData) -> Option<Comp> {
+        let mut prefix = starts[self.rng.below(starts.len())].clone();
+        while !prefix.is_complete(data) {
+            let succs = prefix.successors(data).collect_vec();
+            if succs.is_empty() {
+                return None; // Dead end
+            }
+            let first = &succs[self.rng.below(succs.len())];
+            let second = &succs[self.rng.below(succs.len())];
+            prefix = first.max(second).clone();
+        }
+        prefix.to_comp(data)
+    }
+}
+
+impl SearchStrategy for RandomRestarts {
+    fn search(&mut self, data: &SearchData, comp_fn: &mut dyn FnMut(Comp) -> bool) {
+        let starts = CompPrefix::starts(data);
+        if starts.is_empty() {
+            return;
+        }
+
+        let mut found_comps = HashSet::<(StartIdx, Vec<LinkIdx>)>::new();
+        let mut num_comps = 0;
+        for _ in 0..self.num_restarts {
+            let comp = match self.walk(&starts, data) {
+                Some(c) => c,
+                None => continue,
+            };
+            // Every comp is uniquely determined by its start and sequence of links
+            let links = comp
+                .links
+                .iter()
+                .map(|(link_idx, _)| *link_idx)
+                .collect_vec();
+            if !found_comps.insert((comp.start_idx, links)) {
+                continue; // Comp has already been found
+            }
+
+            if comp_fn(comp) {
+                num_comps += 1;
+            }
+            if num_comps == data.query.num_comps {
+                return; // Stop the search once we've got enough comps
+            }
+        }
+        log::info!("Found {} compositions", num_comps);
+    }
+}
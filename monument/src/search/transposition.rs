@@ -0,0 +1,135 @@
+//! Transposition tables, which detect when several prefixes reach the same search state so that
+//! only the best of them has to be expanded.
+
+use std::collections::HashMap;
+
+use bit_vec::BitVec;
+use log::log;
+
+use crate::{layout::Rotation, music::Score, music::Stroke, utils::RowCounts, Transposition};
+
+use super::{falseness::Unreachable, graph::NodeIdx, prefix::CompPrefix, SearchData};
+
+/// The parts of a [`CompPrefix`] which determine how it can be extended into compositions.  Two
+/// prefixes with the same `StateKey` can be extended in exactly the same ways (unless lengths
+/// are being compared separately by [`Transposition::Dominance`]).
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub(super) struct StateKey {
+    pub(super) node_idx: NodeIdx,
+    /// The length of the prefix.  This is `None` when using [`Transposition::Dominance`], in
+    /// which case the lengths are compared directly.
+    pub(super) length: Option<u32>,
+    pub(super) len_since_non_duffer: u32,
+    /// The number of rows of each method.  If there's only one method then this is determined by
+    /// the length, so is omitted along with `length`.
+    pub(super) method_counts: Option<RowCounts>,
+    pub(super) unreachable_nodes: Unreachable,
+    pub(super) place_bells: BitVec,
+    pub(super) changes_of_method: u32,
+    pub(super) required_rows: u64,
+    pub(super) course_counts: Vec<u32>,
+    pub(super) rotation: Rotation,
+    /// The stroke of the first row after the prefix, which determines the scores of every node
+    /// and link which follows it
+    pub(super) next_stroke: Stroke,
+}
+
+/// A hash table storing the best prefixes which have reached each search state.  Any prefix which
+/// is no better than one already in the table can be discarded without expanding it.
+///
+/// Forgetting states only means that some equivalent prefixes are expanded more than once, so the
+/// table is cleared whenever it reaches its capacity (rather than using unbounded memory).
+#[derive(Debug, Clone)]
+pub(super) struct TranspositionTable {
+    mode: Transposition,
+    /// The maximum number of states stored before the table is cleared
+    capacity: usize,
+    /// For every state, the `(length, score)` of each prefix which isn't dominated by another
+    /// prefix in that state.  For [`Transposition::Exact`], every state only stores one prefix.
+    entries: HashMap<StateKey, Vec<(u32, Score)>>,
+}
+
+impl TranspositionTable {
+    pub(super) fn new(data: &SearchData, capacity: usize) -> Self {
+        Self {
+            mode: data.transposition,
+            capacity,
+            entries: HashMap::new(),
+        }
+    }
+
+    /// Returns `true` if `prefix` should be expanded, i.e. no equivalent prefix which is at least
+    /// as good has already been added to the table.  Every prefix which returns `true` is added to
+    /// the table.
+    ///
+    /// For [`Transposition::Dominance`], a prefix is at least as good as another if it's no longer
+    /// and has at least as much score.  This is only used if no extension can decrease a prefix's
+    /// score (see [`run_query`](crate::run_query)), in which case extending both prefixes in the
+    /// same way will also give the shorter one at least as high an average score.
+    pub(super) fn check(&mut self, prefix: &CompPrefix, data: &SearchData) -> bool {
+        let include_length = match self.mode {
+            Transposition::Off => return true,
+            Transposition::Exact => true,
+            Transposition::Dominance => false,
+        };
+        if self.entries.len() >= self.capacity {
+            log::debug!("Clearing transposition table");
+            self.entries.clear();
+        }
+        let key = prefix.state_key(include_length, data);
+        let (length, score) = (prefix.length, prefix.score());
+
+        let entries = self.entries.entry(key).or_default();
+        if entries.iter().any(|&(l, s)| l <= length && s >= score) {
+            return false; // `prefix` is dominated by a prefix we've already seen
+        }
+        // Remove any prefixes which are dominated by `prefix`
+        entries.retain(|&(l, s)| !(length <= l && score >= s));
+        entries.push((length, score));
+        true
+    }
+
+    /// The number of distinct search states which have been reached
+    pub(super) fn len(&self) -> usize {
+        self.entries.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bellframe::Stage;
+
+    use crate::{
+        test_utils::{config, layout, plain_bob, query, run, runs},
+        Config, Strategy, Transposition,
+    };
+
+    /// Transposition tables only discard prefixes which can't do better than one which has already
+    /// been expanded, so exact searches should find equally good compositions in every mode
+    #[test]
+    fn same_best_comps() {
+        let mut query = query(
+            layout(&[plain_bob(Stage::MINOR)], "123456", "123456"),
+            0..150,
+        );
+        query.num_comps = 10;
+        query.music_types = vec![runs(Stage::MINOR, 4)];
+        query.max_duffer_rows = Some(36);
+
+        let best_scores = |transposition: Transposition| {
+            let comps = run(
+                query.clone(),
+                Config {
+                    strategy: Strategy::DepthFirst,
+                    transposition,
+                    ..config()
+                },
+            );
+            comps.iter().map(|c| c.avg_score).collect::<Vec<_>>()
+        };
+        let scores = best_scores(Transposition::Off);
+        assert_eq!(scores.len(), 10);
+        assert_eq!(best_scores(Transposition::Exact), scores);
+        assert_eq!(best_scores(Transposition::Dominance), scores);
+    }
+}
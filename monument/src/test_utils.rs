@@ -0,0 +1,143 @@
+//! Helpers for building small [`Query`]s in unit tests.
+
+use std::{ops::Range, sync::Arc};
+
+use bellframe::{
+    method::LABEL_LEAD_END, music::Regex, Bell, Mask, Method, PlaceNot, RowBuf, Stage,
+};
+
+use crate::{
+    layout::{
+        new::{coursewise, Call, SpliceStyle},
+        Layout,
+    },
+    music::{MusicType, Patterns, StrokeSet},
+    Comp, Config, OptRange, Query,
+};
+
+/// Parse a [`Method`] (labelled at the lead end, so that calls can be made there)
+pub(crate) fn method(name: &str, stage: Stage, place_notation: &str) -> (Method, String) {
+    let mut method = Method::from_place_not_string(name.to_owned(), stage, place_notation).unwrap();
+    method.set_label(0, Some(LABEL_LEAD_END.to_owned()));
+    let shorthand = name.chars().next().unwrap().to_string();
+    (method, shorthand)
+}
+
+pub(crate) fn plain_bob(stage: Stage) -> (Method, String) {
+    let place_notation = if stage == Stage::MINOR {
+        "x16x16x16,12"
+    } else if stage == Stage::MAJOR {
+        "x18x18x18x18,12"
+    } else {
+        unimplemented!("Plain Bob on {:?}", stage)
+    };
+    method("Plain Bob", stage, place_notation)
+}
+
+/// Generate a course-wise [`Layout`] with near bobs and singles, where any course with the tenor
+/// at home can be rung.  The calls have no weight, so all the score comes from the [`Query`].
+/// `start_row` and `end_row` are parsed as [`Row`](bellframe::Row)s.
+pub(crate) fn layout(methods: &[(Method, String)], start_row: &str, end_row: &str) -> Layout {
+    let stage = methods[0].0.stage();
+    let ch_mask = Mask::fix_bells(stage, vec![Bell::tenor(stage)]);
+    custom_layout(
+        methods,
+        SpliceStyle::LeadLabels,
+        ch_mask,
+        start_row,
+        end_row,
+    )
+}
+
+/// Like [`layout`], but with a custom [`SpliceStyle`] and allowing any course head which matches
+/// `ch_mask`
+pub(crate) fn custom_layout(
+    methods: &[(Method, String)],
+    splice_style: SpliceStyle,
+    ch_mask: Mask,
+    start_row: &str,
+    end_row: &str,
+) -> Layout {
+    let stage = methods[0].0.stage();
+    let mut calls = vec![
+        Call::lead_end_bob(PlaceNot::parse("14", stage).unwrap()),
+        Call::lead_end_single(PlaceNot::parse("1234", stage).unwrap()),
+    ];
+    for call in &mut calls {
+        call.set_weight(0.0);
+    }
+    let tenor = Bell::tenor(stage);
+    coursewise::coursewise(
+        methods,
+        &calls,
+        splice_style,
+        vec![(ch_mask, tenor)],
+        Some(&[0]),
+        None,
+        &RowBuf::parse_with_stage(start_row, stage).unwrap(),
+        &RowBuf::parse_with_stage(end_row, stage).unwrap(),
+    )
+    .unwrap()
+}
+
+/// A [`Query`] for single-part compositions from `layout`, with no music and no constraints
+/// other than length
+pub(crate) fn query(layout: Layout, len_range: Range<usize>) -> Query {
+    let stage = layout.stage;
+    Query {
+        layout,
+        part_head: RowBuf::rounds(stage),
+        method_count_range: 0..len_range.end,
+        len_range,
+        num_comps: 100,
+        max_overlap: None,
+
+        changes_of_method: OptRange::default(),
+        splice_weight: 0.0,
+        atw: false,
+        atw_weight: 0.0,
+        course_head_row_weights: Vec::new(),
+        music_types: Vec::new(),
+        max_duffer_rows: None,
+        required_rows: Vec::new(),
+        forbidden_rows: Vec::new(),
+        course_head_counts: Vec::new(),
+        comp_prefix: None,
+    }
+}
+
+/// A [`Config`] which runs on one thread, so that searches are deterministic
+pub(crate) fn config() -> Config {
+    Config {
+        num_threads: Some(1),
+        ..Config::default()
+    }
+}
+
+/// A [`MusicType`] which scores one point for every run of `len` bells at the front or back of a
+/// row
+pub(crate) fn runs(stage: Stage, len: usize) -> MusicType {
+    MusicType::new(
+        Patterns::Rows(Regex::runs(stage, len, false)),
+        1.0,
+        OptRange::default(),
+        true,
+        StrokeSet::Both,
+    )
+}
+
+/// Run `query`, returning the compositions in ascending order of goodness
+pub(crate) fn run(query: Query, mut config: Config) -> Vec<Comp> {
+    crate::run_query(Arc::new(query), &mut config, None).unwrap()
+}
+
+/// The display strings of a set of compositions, sorted so that searches can be compared
+/// regardless of the order in which they find compositions
+pub(crate) fn comp_strings(comps: &[Comp], layout: &Layout) -> Vec<String> {
+    let mut strings = comps
+        .iter()
+        .map(|c| c.display_string(layout))
+        .collect::<Vec<_>>();
+    strings.sort();
+    strings
+}
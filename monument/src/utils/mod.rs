@@ -22,6 +22,35 @@ pub fn coprime_bitmap(n: u16) -> u64 {
     mask
 }
 
+/// A small and fast pseudo-random number generator (using the xorshift64* algorithm).  The
+/// randomness isn't of very high quality, but it's more than good enough to randomise a search.
+#[derive(Debug, Clone)]
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        // xorshift gets stuck if its state is 0, so mix the seed with an arbitrary odd number
+        Self {
+            state: seed ^ 0x9E37_79B9_7F4A_7C15,
+        }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        self.state.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    /// Returns a random number in `0..n`.  Panics if `n` is 0.
+    pub fn below(&mut self, n: usize) -> usize {
+        assert!(n > 0);
+        (self.next_u64() % n as u64) as usize
+    }
+}
+
 /// A container type which sorts its contents according to some given [`Distance`] metric
 #[derive(Debug, Clone)]
 pub struct FrontierItem<T> {
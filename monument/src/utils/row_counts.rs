@@ -21,6 +21,10 @@ impl RowCounts {
         cnts
     }
 
+    pub fn from_counts(counts: Vec<usize>) -> Self {
+        RowCounts { counts }
+    }
+
     pub fn counts(&self) -> &[usize] {
         &self.counts
     }
@@ -65,6 +69,15 @@ impl AddAssign<&RowCounts> for RowCounts {
     }
 }
 
+impl AddAssign<&[usize]> for RowCounts {
+    fn add_assign(&mut self, rhs: &[usize]) {
+        self.counts
+            .iter_mut()
+            .zip_eq(rhs)
+            .for_each(|(lhs, inc)| *lhs += *inc);
+    }
+}
+
 impl Mul<usize> for &RowCounts {
     type Output = RowCounts;
 